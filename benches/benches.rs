@@ -1,10 +1,13 @@
-use criterion::{criterion_group, criterion_main, Criterion};
-use patterns::{count_frequency, generate_pattern};
+use criterion::{criterion_group, criterion_main, Benchmark, Criterion};
+use patterns::{
+    count_frequency, count_frequency_packed, count_frequency_sharded, count_frequency_sorted,
+    file_to_pattern_set, generate_pattern, Symbols,
+};
 
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("pattern generation", |bencher| {
         let string = "LALALAXOXOXO";
-        bencher.iter(|| generate_pattern(&string));
+        bencher.iter(|| generate_pattern(string).unwrap());
     });
 
     c.bench_function("raw counts", |bencher| {
@@ -15,6 +18,33 @@ fn criterion_benchmark(c: &mut Criterion) {
         ];
         bencher.iter(|| count_frequency(&v))
     });
+
+    c.bench_function("packed counts", |bencher| {
+        let v = vec![
+            vec![0, 0, 1, 0, 0],
+            vec![0, 0, 1, 0, 0, 0],
+            vec![0, 0, 1, 0, 0],
+        ];
+        bencher.iter(|| count_frequency_packed(&v))
+    });
+
+    // the full corpus: a single map filled on one thread, versus shards filled in parallel, versus sorting
+    let corpus = file_to_pattern_set("words.txt", Symbols::Ascii).unwrap();
+    let sharded = corpus.clone();
+    let sorted = corpus.clone();
+    c.bench(
+        "corpus counts",
+        Benchmark::new("single map", move |bencher| {
+            bencher.iter(|| count_frequency(&corpus))
+        })
+        .with_function("sharded", move |bencher| {
+            bencher.iter(|| count_frequency_sharded(&sharded))
+        })
+        .with_function("sorted", move |bencher| {
+            bencher.iter(|| count_frequency_sorted(&sorted))
+        })
+        .sample_size(10),
+    );
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,66 @@
+use crate::{checked_patterns, Pattern, PatternError, Symbols, TrustedHasher};
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+/// A string, with its pattern and pattern class
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Annotation<'a> {
+    /// The 1-based position of the string in the input
+    pub line: usize,
+    pub string: &'a str,
+    pub pattern: Pattern,
+    /// The pattern class: classes are numbered from 0 in order of their first member,
+    /// so ids are stable for a given input
+    pub class: usize,
+    /// The number of other strings with the same pattern
+    pub friends: u32,
+}
+
+/// Annotate each string with its pattern, its class, and its number of friends, in input order
+pub fn annotate<'a>(
+    strings: &[&'a str],
+    symbols: Symbols,
+) -> Result<Vec<Annotation<'a>>, PatternError> {
+    annotate_with_hasher::<TrustedHasher>(strings, symbols)
+}
+
+/// Annotate each string using the given hasher
+pub fn annotate_with_hasher<'a, S>(
+    strings: &[&'a str],
+    symbols: Symbols,
+) -> Result<Vec<Annotation<'a>>, PatternError>
+where
+    S: BuildHasher + Default,
+{
+    let patterns = checked_patterns::<S, _>(strings, &symbols)?;
+    // each pattern's class id, and each class's size
+    let mut classes: HashMap<&[u8], usize, S> =
+        HashMap::with_capacity_and_hasher(patterns.len(), S::default());
+    let mut sizes: Vec<u32> = vec![];
+    let ids: Vec<usize> = patterns
+        .iter()
+        .map(|pattern| {
+            let id = *classes.entry(pattern).or_insert_with(|| {
+                sizes.push(0);
+                sizes.len() - 1
+            });
+            sizes[id] += 1;
+            id
+        })
+        .collect();
+    drop(classes);
+    let annotations = patterns
+        .into_iter()
+        .zip(strings)
+        .zip(ids)
+        .enumerate()
+        .map(|(idx, ((pattern, string), class))| Annotation {
+            line: idx + 1,
+            string,
+            pattern: pattern.into(),
+            class,
+            friends: sizes[class] - 1,
+        })
+        .collect();
+    Ok(annotations)
+}
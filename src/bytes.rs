@@ -0,0 +1,83 @@
+use crate::PatternError;
+use rayon::prelude::*;
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::Path;
+
+/// How a buffer of binary data is divided into records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Records {
+    /// Records are separated by a delimiter byte. A trailing delimiter doesn't start an empty record
+    Delimited(u8),
+    /// Records are chunks of a fixed width. The final record may be shorter
+    Fixed(NonZeroUsize),
+}
+
+impl Records {
+    /// Divide a buffer into records
+    pub fn split<'a>(&self, data: &'a [u8]) -> Vec<&'a [u8]> {
+        match *self {
+            Records::Delimited(delimiter) => {
+                let trimmed = data.strip_suffix(&[delimiter]).unwrap_or(data);
+                if data.is_empty() {
+                    vec![]
+                } else {
+                    trimmed.split(|&byte| byte == delimiter).collect()
+                }
+            }
+            Records::Fixed(width) => data.chunks(width.get()).collect(),
+        }
+    }
+}
+
+/// Generate a pattern from a sequence of arbitrary bytes
+///
+/// All 256 byte values are valid symbols. Indices are encoded as in
+/// `generate_pattern_of`, so a record containing only ASCII bytes has the same
+/// pattern that `generate_pattern` would produce.
+#[inline]
+pub fn generate_byte_pattern(haystack: &[u8]) -> Vec<u8> {
+    // as in generate_pattern, a "seen" value of 0 means the byte hasn't been seen yet
+    let mut total = 0u16;
+    let mut stack = [0u16; 256];
+    let mut pattern = Vec::with_capacity(haystack.len());
+    for byte in haystack {
+        let mut needle = stack[*byte as usize];
+        if needle == 0 {
+            total += 1;
+            stack[*byte as usize] = total;
+            needle = total;
+        }
+        // there are at most 256 distinct bytes, so an index needs at most two bytes
+        let index = needle - 1;
+        if index < 0x80 {
+            pattern.push(index as u8)
+        } else {
+            pattern.push((index as u8 & 0x7F) | 0x80);
+            pattern.push((index >> 7) as u8);
+        }
+    }
+    pattern
+}
+
+/// Divide a buffer of binary data into records, and generate a pattern for each of them
+///
+/// Panics if the records have a fixed width of 0: see `Records::split`.
+pub fn bytes_to_patterns(data: &[u8], records: Records) -> Vec<Vec<u8>> {
+    records
+        .split(data)
+        .par_iter()
+        .map(|record| generate_byte_pattern(record))
+        .collect()
+}
+
+/// Attempt to open a binary file, read it, and parse its records into a vec of patterns
+///
+/// Panics if the records have a fixed width of 0: see `Records::split`.
+pub fn file_to_byte_patterns<P>(filename: P, records: Records) -> Result<Vec<Vec<u8>>, PatternError>
+where
+    P: AsRef<Path>,
+{
+    let data = fs::read(filename)?;
+    Ok(bytes_to_patterns(&data, records))
+}
@@ -0,0 +1,139 @@
+use crate::{line_to_pattern_into, split_lines, PatternError, PatternSet, RejectedLine, Symbols};
+use memmap2::Mmap;
+use rayon::prelude::*;
+use std::fs::File;
+use std::hash::BuildHasher;
+use std::io::Read;
+use std::path::Path;
+
+/// The approximate size of the newline-aligned chunks which are parsed as single tasks
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Patterns and rejected lines from a single chunk, with line numbers relative to the chunk
+struct ParsedChunk {
+    lines: usize,
+    patterns: PatternSet,
+    rejected: Vec<RejectedLine>,
+}
+
+/// Memory-map a file, and parse it in newline-aligned chunks, each on its own task
+///
+/// Anything which isn't a regular file, such as a pipe, can't be mapped, so it's read into
+/// memory instead. Rejected lines are returned in line order, numbered by their position in the file.
+pub(crate) fn map_and_parse<S, P>(
+    filename: P,
+    symbols: &Symbols,
+) -> Result<(PatternSet, Vec<RejectedLine>), PatternError>
+where
+    S: BuildHasher + Default,
+    P: AsRef<Path>,
+{
+    let mut file = File::open(filename)?;
+    if !file.metadata()?.is_file() {
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+        return Ok(parse_chunked::<S>(&bytes, symbols));
+    }
+    // SAFETY: the map is read-only, and doesn't outlive this function. As with any
+    // memory map, the file mustn't be truncated by another process while it's being read
+    let map = unsafe { Mmap::map(&file)? };
+    Ok(parse_chunked::<S>(&map, symbols))
+}
+
+/// Parse a buffer in newline-aligned chunks, each on its own task
+pub(crate) fn parse_chunked<S>(bytes: &[u8], symbols: &Symbols) -> (PatternSet, Vec<RejectedLine>)
+where
+    S: BuildHasher + Default,
+{
+    let chunks: Vec<ParsedChunk> = newline_chunks(bytes, CHUNK_SIZE)
+        .par_iter()
+        .map(|chunk| parse_chunk::<S>(chunk, symbols))
+        .collect();
+    let mut patterns = PatternSet::with_capacity(
+        chunks.iter().map(|chunk| chunk.patterns.len()).sum(),
+        chunks
+            .iter()
+            .map(|chunk| chunk.patterns.as_bytes().len())
+            .sum(),
+    );
+    let mut rejected = vec![];
+    // line numbers can only be made absolute once the preceding chunks have been counted
+    let mut offset = 0;
+    for chunk in chunks {
+        patterns.append(&chunk.patterns);
+        rejected.extend(chunk.rejected.into_iter().map(|mut reject| {
+            reject.line += offset;
+            reject.error.offset_line(offset);
+            reject
+        }));
+        offset += chunk.lines;
+    }
+    (patterns, rejected)
+}
+
+/// Parse a single chunk of lines
+fn parse_chunk<S>(chunk: &[u8], symbols: &Symbols) -> ParsedChunk
+where
+    S: BuildHasher + Default,
+{
+    let mut parsed = ParsedChunk {
+        lines: 0,
+        // ASCII patterns are the same length as their lines, so this is usually exact
+        patterns: PatternSet::with_capacity(0, chunk.len()),
+        rejected: vec![],
+    };
+    for (idx, line) in split_lines(chunk).enumerate() {
+        let written = parsed
+            .patterns
+            .push_with(|pattern| line_to_pattern_into::<S>(line, idx + 1, symbols, pattern));
+        if let Err(error) = written {
+            parsed.rejected.push(RejectedLine {
+                line: idx + 1,
+                contents: line.to_vec(),
+                error,
+            })
+        }
+        parsed.lines += 1;
+    }
+    parsed
+}
+
+/// Split a buffer into chunks of roughly `size` bytes, each ending with a newline (apart from the last)
+fn newline_chunks(bytes: &[u8], size: usize) -> Vec<&[u8]> {
+    let mut chunks = vec![];
+    let mut rest = bytes;
+    while rest.len() > size {
+        match rest[size..].iter().position(|&byte| byte == b'\n') {
+            Some(idx) => {
+                let (chunk, remainder) = rest.split_at(size + idx + 1);
+                chunks.push(chunk);
+                rest = remainder;
+            }
+            None => break,
+        }
+    }
+    if !rest.is_empty() {
+        chunks.push(rest);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TrustedHasher;
+    #[test]
+    fn test_chunk_line_numbers() {
+        let bytes = b"ABAB\nCD\xFF\nEFEF\n\nGH\xFF\nIJIJ";
+        let chunks = newline_chunks(bytes, 3);
+        assert_eq!(chunks.concat(), bytes.to_vec());
+        assert!(chunks[..chunks.len() - 1]
+            .iter()
+            .all(|chunk| chunk.ends_with(b"\n")));
+        let (patterns, rejected) = parse_chunked::<TrustedHasher>(bytes, &Symbols::Ascii);
+        assert_eq!(patterns.len(), 4);
+        let lines: Vec<_> = rejected.iter().map(|reject| reject.line).collect();
+        assert_eq!(lines, vec![2, 5]);
+        assert_eq!(rejected[1].error.line(), Some(5));
+    }
+}
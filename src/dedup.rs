@@ -0,0 +1,76 @@
+use crate::{
+    checked_numbered_patterns, checked_patterns, hash_count, PatternError, Symbols, Threshold,
+    TrustedHasher,
+};
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
+
+/// How identical strings are counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Duplicates {
+    /// Identical strings are friends of each other, as in `count_frequency`
+    #[default]
+    Friends,
+    /// Each distinct string is counted once
+    Distinct,
+    /// Identical strings are counted separately, but aren't friends of each other: a string is
+    /// only friendly if a different string has the same pattern, i.e. if the bijection between
+    /// them isn't the identity
+    NotFriends,
+}
+
+/// Count friendly strings, treating identical strings as `duplicates` specifies
+pub fn count_strings(
+    strings: &[&str],
+    symbols: Symbols,
+    duplicates: Duplicates,
+) -> Result<u32, PatternError> {
+    count_strings_with_hasher::<TrustedHasher>(strings, symbols, duplicates)
+}
+
+/// Count friendly strings, treating identical strings as `duplicates` specifies, using the given hasher
+pub fn count_strings_with_hasher<S>(
+    strings: &[&str],
+    symbols: Symbols,
+    duplicates: Duplicates,
+) -> Result<u32, PatternError>
+where
+    S: BuildHasher + Default + Sync,
+{
+    match duplicates {
+        Duplicates::Friends => {
+            let patterns = checked_patterns::<S, _>(strings, &symbols)?;
+            Ok(hash_count::<S, _>(&patterns, &Threshold::default()).strings)
+        }
+        Duplicates::Distinct => {
+            let mut seen: HashSet<&str, S> =
+                HashSet::with_capacity_and_hasher(strings.len(), S::default());
+            // keep each string's line number, so that errors refer to its first occurrence
+            let (numbers, distinct): (Vec<usize>, Vec<&str>) = strings
+                .iter()
+                .enumerate()
+                .filter(|(_, string)| seen.insert(string))
+                .map(|(idx, &string)| (idx + 1, string))
+                .unzip();
+            let patterns =
+                checked_numbered_patterns::<S, _, _>(&distinct, |idx| numbers[idx], &symbols)?;
+            Ok(hash_count::<S, _>(&patterns, &Threshold::default()).strings)
+        }
+        Duplicates::NotFriends => {
+            let patterns = checked_patterns::<S, _>(strings, &symbols)?;
+            // each class's size, one of its members, and whether any other member differs from it
+            let mut classes: HashMap<&[u8], (u32, &str, bool), S> =
+                HashMap::with_capacity_and_hasher(patterns.len(), S::default());
+            patterns.iter().zip(strings).for_each(|(pattern, string)| {
+                let (size, first, differs) = classes.entry(pattern).or_insert((0, string, false));
+                *size += 1;
+                *differs |= first != string;
+            });
+            Ok(classes
+                .into_values()
+                .filter(|&(_, _, differs)| differs)
+                .map(|(size, _, _)| size)
+                .sum())
+        }
+    }
+}
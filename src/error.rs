@@ -0,0 +1,121 @@
+use crate::RejectedLine;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Errors which can occur while reading input and generating patterns
+///
+/// Line and column numbers are 1-based, and columns are byte offsets into the line.
+#[derive(Debug)]
+pub enum PatternError {
+    /// The input couldn't be read
+    Io(io::Error),
+    /// A line contains a byte outside the ASCII range
+    InvalidByte {
+        line: usize,
+        column: usize,
+        byte: u8,
+    },
+    /// A line isn't valid UTF-8
+    InvalidUtf8 { line: usize, column: usize },
+    /// One or more lines of the input couldn't be parsed, in line order
+    InvalidLines(Vec<PatternError>),
+    /// One or more query strings couldn't be parsed, in query order. The line number of each
+    /// error is the 1-based position of its query
+    InvalidQueries(Vec<PatternError>),
+    /// A prebuilt index wasn't generated from the strings, or with the symbols, it's used with
+    IndexMismatch(String),
+}
+
+impl PatternError {
+    /// The line number an error refers to, if it refers to a single line
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            PatternError::InvalidByte { line, .. } | PatternError::InvalidUtf8 { line, .. } => {
+                Some(*line)
+            }
+            _ => None,
+        }
+    }
+
+    /// Shift the line number of a single-line error
+    pub(crate) fn offset_line(&mut self, offset: usize) {
+        if let PatternError::InvalidByte { line, .. } | PatternError::InvalidUtf8 { line, .. } =
+            self
+        {
+            *line += offset
+        }
+    }
+
+    /// Describe a single-line error, calling the line a `kind`: as a query, an invalid string
+    /// is described as e.g. "Query 2, column 1: invalid UTF-8"
+    pub fn describe(&self, kind: &str) -> String {
+        struct Described<'a>(&'a PatternError, &'a str);
+        impl fmt::Display for Described<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.write_at(f, self.1)
+            }
+        }
+        Described(self, kind).to_string()
+    }
+
+    /// Write a single-line error, calling the line a `kind`, e.g. "Query 2"
+    fn write_at(&self, f: &mut fmt::Formatter, kind: &str) -> fmt::Result {
+        match self {
+            PatternError::InvalidByte { line, column, byte } => write!(
+                f,
+                "{} {}, column {}: got a non-ASCII byte (0x{:02X})",
+                kind, line, column, byte
+            ),
+            PatternError::InvalidUtf8 { line, column } => {
+                write!(f, "{} {}, column {}: invalid UTF-8", kind, line, column)
+            }
+            other => write!(f, "{}", other),
+        }
+    }
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatternError::Io(err) => write!(f, "Couldn't read input: {}", err),
+            PatternError::InvalidByte { .. } | PatternError::InvalidUtf8 { .. } => {
+                self.write_at(f, "Line")
+            }
+            PatternError::InvalidLines(errors) => write!(f, "{} invalid line(s)", errors.len()),
+            PatternError::InvalidQueries(errors) => {
+                write!(f, "{} invalid query string(s)", errors.len())?;
+                for (idx, err) in errors.iter().enumerate() {
+                    f.write_str(if idx == 0 { ": " } else { "; " })?;
+                    err.write_at(f, "query")?;
+                }
+                Ok(())
+            }
+            PatternError::IndexMismatch(reason) => {
+                write!(f, "The index doesn't match the input: {}", reason)
+            }
+        }
+    }
+}
+
+impl Error for PatternError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PatternError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PatternError {
+    fn from(err: io::Error) -> Self {
+        PatternError::Io(err)
+    }
+}
+
+/// Rejected lines become an `InvalidLines` error holding each line's error, in the same order
+impl From<Vec<RejectedLine>> for PatternError {
+    fn from(rejected: Vec<RejectedLine>) -> Self {
+        PatternError::InvalidLines(rejected.into_iter().map(|reject| reject.error).collect())
+    }
+}
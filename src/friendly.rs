@@ -0,0 +1,130 @@
+use crate::query::split_symbols;
+use crate::Symbols;
+use std::error::Error;
+use std::fmt;
+
+/// Why two strings aren't friends
+///
+/// Positions are 1-based, and count symbols rather than bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Unfriendly<'a> {
+    /// The strings have different numbers of symbols
+    Length { left: usize, right: usize },
+    /// A symbol of the left string would have to map to two different symbols of the right:
+    /// `symbol` already maps to `mapped`, but at `position` it's paired with `other`
+    Conflict {
+        position: usize,
+        symbol: &'a str,
+        mapped: &'a str,
+        other: &'a str,
+    },
+    /// Two symbols of the left string would both map to the same symbol of the right:
+    /// `mapped` is already the image of `other`, but at `position` it's paired with `symbol`
+    Collision {
+        position: usize,
+        symbol: &'a str,
+        other: &'a str,
+        mapped: &'a str,
+    },
+}
+
+impl<'a> fmt::Display for Unfriendly<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Unfriendly::Length { left, right } => write!(
+                f,
+                "the strings have different lengths ({} and {} symbols)",
+                left, right
+            ),
+            Unfriendly::Conflict {
+                position,
+                symbol,
+                mapped,
+                other,
+            } => write!(
+                f,
+                "at position {}, {:?} would map to both {:?} and {:?}",
+                position, symbol, mapped, other
+            ),
+            Unfriendly::Collision {
+                position,
+                symbol,
+                other,
+                mapped,
+            } => write!(
+                f,
+                "at position {}, {:?} and {:?} would both map to {:?}",
+                position, other, symbol, mapped
+            ),
+        }
+    }
+}
+
+impl<'a> Error for Unfriendly<'a> {}
+
+/// Check whether two strings are friends, treating each char as a symbol
+///
+/// If they are, returns the bijection from the symbols of `left` to those of `right`, in order of
+/// first appearance. If they aren't, returns the first reason found, reading from the start.
+// "HHHH" and "BOBO" aren't friends: at position 2, "H" would map to both "B" and "O"
+pub fn are_friendly<'a>(
+    left: &'a str,
+    right: &'a str,
+) -> Result<Vec<(&'a str, &'a str)>, Unfriendly<'a>> {
+    are_friendly_with(left, right, Symbols::Chars)
+}
+
+/// Check whether two strings are friends using the given symbols
+///
+/// Nothing is validated, so `Symbols::Ascii` behaves like `Symbols::Chars`.
+pub fn are_friendly_with<'a>(
+    left: &'a str,
+    right: &'a str,
+    symbols: Symbols,
+) -> Result<Vec<(&'a str, &'a str)>, Unfriendly<'a>> {
+    bijection(
+        &split_symbols(left, &symbols),
+        &split_symbols(right, &symbols),
+    )
+}
+
+/// Pair each distinct symbol of one sequence with the symbol of the other at the same position,
+/// failing if the pairing isn't one-to-one
+pub(crate) fn bijection<'a>(
+    left: &[&'a str],
+    right: &[&'a str],
+) -> Result<Vec<(&'a str, &'a str)>, Unfriendly<'a>> {
+    if left.len() != right.len() {
+        return Err(Unfriendly::Length {
+            left: left.len(),
+            right: right.len(),
+        });
+    }
+    let mut pairs: Vec<(&str, &str)> = vec![];
+    for (idx, (&symbol, &target)) in left.iter().zip(right).enumerate() {
+        match pairs
+            .iter()
+            .find(|&&(from, to)| from == symbol || to == target)
+        {
+            None => pairs.push((symbol, target)),
+            Some(&(from, to)) if from == symbol && to == target => (),
+            Some(&(from, to)) if from == symbol => {
+                return Err(Unfriendly::Conflict {
+                    position: idx + 1,
+                    symbol,
+                    mapped: to,
+                    other: target,
+                })
+            }
+            Some(&(from, _)) => {
+                return Err(Unfriendly::Collision {
+                    position: idx + 1,
+                    symbol,
+                    other: from,
+                    mapped: target,
+                })
+            }
+        }
+    }
+    Ok(pairs)
+}
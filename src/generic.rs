@@ -0,0 +1,111 @@
+use crate::TrustedHasher;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+
+/// Generate a pattern from a sequence of any hashable symbols
+///
+/// Each distinct symbol is mapped to its order of first appearance. Indices are
+/// written as LEB128 varints: indices below 128 take a single byte, and larger ones
+/// take as many bytes as they need. The encoding is prefix-free, so two patterns are
+/// equal exactly when their index sequences are. Sequences of up to 128 distinct
+/// symbols have the same pattern as the equivalent ASCII string has in `generate_pattern`,
+/// so patterns from every generator in this crate can be counted together.
+// [10, 20, 10, 20] generates a pattern of 0101
+// ["the", "cat", "the"] generates a pattern of 010
+pub fn generate_pattern_of<T, I>(items: I) -> Vec<u8>
+where
+    T: Hash + Eq,
+    I: IntoIterator<Item = T>,
+{
+    generate_pattern_of_with_hasher::<TrustedHasher, T, I>(items)
+}
+
+/// Generate a pattern from a sequence of any hashable symbols, hashing them with the given hasher
+///
+/// Use `UntrustedHasher` for untrusted input, so that symbols chosen to collide can't make this quadratic.
+pub fn generate_pattern_of_with_hasher<S, T, I>(items: I) -> Vec<u8>
+where
+    S: BuildHasher + Default,
+    T: Hash + Eq,
+    I: IntoIterator<Item = T>,
+{
+    let items = items.into_iter();
+    let mut seen: HashMap<T, u32, S> = HashMap::default();
+    let mut pattern = Vec::with_capacity(items.size_hint().0);
+    for item in items {
+        let total = seen.len() as u32;
+        push_index(&mut pattern, *seen.entry(item).or_insert(total));
+    }
+    pattern
+}
+
+/// Append a symbol index to a pattern as a LEB128 varint
+pub(crate) fn push_index(pattern: &mut Vec<u8>, mut index: u32) {
+    while index >= 0x80 {
+        pattern.push((index as u8 & 0x7F) | 0x80);
+        index >>= 7;
+    }
+    pattern.push(index as u8);
+}
+
+/// Decode the symbol indices of a pattern
+///
+/// A truncated final index (which can't be produced by any of the generators) is ignored.
+/// Decoding ends at an index longer than the five bytes a `u32` can need, since no generator
+/// can produce that either.
+pub fn pattern_indices(pattern: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    let mut bytes = pattern.iter();
+    std::iter::from_fn(move || {
+        let mut index = 0u32;
+        let mut shift = 0;
+        loop {
+            if shift >= 32 {
+                // skip the rest, so that the iterator stays finished
+                bytes = [].iter();
+                return None;
+            }
+            let byte = *bytes.next()?;
+            index |= u32::from(byte & 0x7F) << shift;
+            if byte < 0x80 {
+                return Some(index);
+            }
+            shift += 7;
+        }
+    })
+}
+
+/// Order two patterns by their symbol indices, which is the order of their letter forms
+///
+/// Byte order differs from this once an index needs more than one byte: the pattern with index 255
+/// is `[0xFF, 0x01]` and that with 256 is `[0x80, 0x02]`. Patterns with the same indices (which can
+/// only differ in malformed trailing bytes) fall back to byte order, to stay consistent with `Eq`.
+pub(crate) fn compare_patterns(left: &[u8], right: &[u8]) -> Ordering {
+    pattern_indices(left)
+        .cmp(pattern_indices(right))
+        .then_with(|| left.cmp(right))
+}
+
+/// Write a pattern in letter form, e.g. "ABAB" for the pattern of "XYXY"
+///
+/// Indices 0 to 25 are written as the letters A to Z, and 26 to 51 as a to z.
+/// Any larger index is written in decimal, in braces: the 53rd symbol is "{52}".
+pub fn pattern_letters(pattern: &[u8]) -> String {
+    let mut letters = String::with_capacity(pattern.len());
+    // writing to a String can't fail
+    write_letters(pattern, &mut letters).unwrap();
+    letters
+}
+
+/// Write a pattern in letter form to any formatter
+pub(crate) fn write_letters<W: fmt::Write>(pattern: &[u8], out: &mut W) -> fmt::Result {
+    for index in pattern_indices(pattern) {
+        match index {
+            0..=25 => out.write_char(char::from(b'A' + index as u8))?,
+            26..=51 => out.write_char(char::from(b'a' + (index - 26) as u8))?,
+            _ => write!(out, "{{{}}}", index)?,
+        }
+    }
+    Ok(())
+}
@@ -1,24 +1,1550 @@
 // compile using CARGO_INCREMENTAL="0" cargo build --release
 
-use clap::{crate_version, value_t, App, Arg};
-use patterns::{count_frequency, file_to_patterns};
+use clap::{crate_version, App, AppSettings, Arg, ArgMatches, SubCommand};
+use patterns::{
+    annotate_with_hasher, are_friendly_with, bytes_to_lines, bytes_to_patterns,
+    class_mask_with_hasher, count_classes_with_hasher, count_reader_classes_with_hasher,
+    count_strings_with_hasher, file_to_pattern_set_with_hasher,
+    file_to_patterns_lenient_with_hasher, frequency_report_with_hasher, friend_groups_with_hasher,
+    query_friends_with_hasher, query_index_with_hasher, report_reader_lenient_with_hasher,
+    strings_to_patterns_with_hasher, top_patterns_reader_with_hasher, top_patterns_with_hasher,
+    Annotation, ClassCount, Duplicates, FrequencyReport, Pattern, PatternError, PatternSet,
+    Records, RejectedLine, SetOrigin, Strategy, Symbols, Threshold, TrustedHasher, Unfriendly,
+    UntrustedHasher, TOP_EXAMPLES,
+};
+use regex::Regex;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::BuildHasher;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::num::NonZeroUsize;
+use std::process;
+use std::time::{Duration, Instant};
 
 fn main() {
     // Generate a CLI, and get input filename to process
     let params = App::new("patterns")
         .version(crate_version!())
         .author("Stephan Hügel <urschrei@gmail.com>")
-        .about("Generate a frequency count of patterns derived from ASCII strings")
-        .arg(
-            Arg::with_name("INPUT_STRINGS")
-                .help("A text file containing ASCII uppercase strings, one per line")
-                .index(1)
-                .required(true),
+        .about("Generate a frequency count of patterns derived from strings")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .subcommand(
+            SubCommand::with_name("count")
+                .about("Count the friendly strings: strings whose pattern is shared by at least one other string")
+                .args(&input_args())
+                .args(&parse_args())
+                .arg(
+                    Arg::with_name("STRATEGY")
+                        .help("How patterns are counted. Streamed input is always counted using a single hash map")
+                        .long("strategy")
+                        .takes_value(true)
+                        .possible_values(&["auto", "hash", "sharded", "packed", "sort"])
+                        .default_value("auto"),
+                )
+                .args(&size_args())
+                .arg(
+                    Arg::with_name("DISTINCT")
+                        .help("Count each distinct string once, so that identical lines aren't friends")
+                        .long("distinct")
+                        .conflicts_with_all(&["LENIENT", "BINARY", "STREAM", "STRATEGY", "MIN_SIZE", "MAX_SIZE"]),
+                )
+                .arg(
+                    Arg::with_name("EXCLUDE_IDENTICAL")
+                        .help("Count identical lines separately, but only count a line as friendly if a different line has the same pattern")
+                        .long("exclude-identical")
+                        .conflicts_with_all(&["DISTINCT", "LENIENT", "BINARY", "STREAM", "STRATEGY", "MIN_SIZE", "MAX_SIZE"]),
+                )
+                .arg(format_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("groups")
+                .about("Print each friend group, with its pattern and the line number of each member")
+                .args(&input_args())
+                .args(&size_args())
+                .arg(format_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("annotate")
+                .about("Print each input line with its pattern, the id of its pattern class, and its number of friends")
+                .args(&input_args())
+                .arg(
+                    Arg::with_name("FORMAT")
+                        .help("The output format: tab-separated, comma-separated, JSON Lines, or a single versioned JSON document")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["tsv", "csv", "jsonl", "json"])
+                        .default_value("tsv"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("query")
+                .about("Print the input lines which are friends of the given strings, with the bijection between each pair")
+                .args(&input_args())
+                .arg(
+                    Arg::with_name("QUERIES")
+                        .help("A string to look up. Can be given more than once")
+                        .long("query")
+                        .short("q")
+                        .takes_value(true)
+                        .value_name("STRING")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("INDEX")
+                        .help("Read the input's patterns from an index written by the index subcommand, instead of generating them")
+                        .long("index")
+                        .takes_value(true)
+                        .value_name("FILE"),
+                )
+                .arg(format_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("index")
+                .about("Generate the pattern of every input line, and write them to a file which query can read. Use the same symbols when querying")
+                .args(&input_args())
+                .arg(
+                    Arg::with_name("OUTPUT")
+                        .help("The index file to write")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .required(true),
+                )
+                .arg(format_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("explain")
+                .about("Explain whether two strings are friends: print the bijection between their symbols, or the first position at which there can't be one")
+                .arg(
+                    Arg::with_name("LEFT")
+                        .help("The first string")
+                        .index(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("RIGHT")
+                        .help("The second string")
+                        .index(2)
+                        .required(true),
+                )
+                .args(&symbol_args())
+                .arg(format_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Print statistics of the pattern classes: distinct patterns, friend pairs, singletons, the largest class, and a histogram of class sizes")
+                .args(&input_args())
+                .args(&parse_args())
+                .arg(format_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("top")
+                .about("Print the most common patterns, with their counts and example strings")
+                .args(&input_args())
+                .arg(
+                    Arg::with_name("NUMBER")
+                        .help("The number of patterns to print")
+                        .long("number")
+                        .short("n")
+                        .takes_value(true)
+                        .value_name("N")
+                        .validator(parse_size)
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::with_name("STREAM")
+                        .help("Stream the input file, summarising it in bounded memory, so that counts may be overestimates. Input from stdin is always streamed")
+                        .long("stream"),
+                )
+                .arg(
+                    Arg::with_name("CAPACITY")
+                        .help("The number of patterns tracked while summarising streamed input. Any pattern occurring more than (lines / K) times is guaranteed to be found")
+                        .long("capacity")
+                        .takes_value(true)
+                        .value_name("K")
+                        .validator(parse_size)
+                        .default_value("65536"),
+                )
+                .arg(format_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("filter")
+                .about("Print only the input lines which have at least one friend (or which are in classes of the sizes given by --min-size and --max-size, or which have one of the patterns given by --pattern), in input order")
+                .args(&input_args())
+                .args(&size_args())
+                .arg(
+                    Arg::with_name("PATTERNS")
+                        .help("Select only lines with this pattern, written in letter form, e.g. ABAB. Can be given more than once")
+                        .long("pattern")
+                        .short("p")
+                        .takes_value(true)
+                        .value_name("PATTERN")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .validator(|value| value.parse::<Pattern>().map(|_| ()).map_err(|err| err.to_string()))
+                        .conflicts_with_all(&["MIN_SIZE", "MAX_SIZE"]),
+                )
+                .arg(
+                    Arg::with_name("INVERT")
+                        .help("Print only the lines which would otherwise be omitted")
+                        .long("invert")
+                        .short("v"),
+                )
+                .arg(
+                    Arg::with_name("LINE_NUMBERS")
+                        .help("Prefix each line with its line number and a tab")
+                        .long("line-numbers")
+                        .short("n"),
+                )
+                .arg(format_arg()),
         )
         .get_matches();
-    let input_file = value_t!(params.value_of("INPUT_STRINGS"), String).unwrap();
-    let strings = file_to_patterns(&input_file);
+    let (command, args) = params.subcommand();
+    let args = args.unwrap();
+    let result = if args.is_present("UNTRUSTED") {
+        run::<UntrustedHasher>(command, args)
+    } else {
+        run::<TrustedHasher>(command, args)
+    };
+    match result {
+        // output piped into a command which has stopped reading, such as head, isn't an error
+        Err(PatternError::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => (),
+        Err(err) => {
+            match args.value_of("FORMAT") {
+                Some("json" | "jsonl") => report_json(command, &err),
+                _ => report(&err),
+            }
+            process::exit(1);
+        }
+        Ok(()) => (),
+    }
+}
+
+/// The options shared by every subcommand which reads input, which select the input and how it's split into symbols
+fn input_args() -> Vec<Arg<'static, 'static>> {
+    let mut args = vec![Arg::with_name("INPUT_STRINGS")
+        .help("A text file containing strings (ASCII uppercase by default), one per line. Use - to read from stdin")
+        .index(1)
+        .default_value("-")];
+    args.extend(symbol_args());
+    args.push(
+        Arg::with_name("UNTRUSTED")
+            .help("Hash patterns and symbols with randomly-keyed SipHash, which resists hash-flooding attacks on untrusted input, instead of the faster FNV")
+            .long("untrusted")
+            .short("u"),
+    );
+    args
+}
+
+/// The options which select how strings are split into symbols
+fn symbol_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("SYMBOLS")
+            .help("The unit treated as a single symbol: ASCII bytes, Unicode chars, grapheme clusters, or whitespace-separated words")
+            .long("symbols")
+            .short("s")
+            .takes_value(true)
+            .possible_values(&["ascii", "chars", "graphemes", "words"])
+            .default_value("ascii"),
+        Arg::with_name("SEPARATOR")
+            .help("Split each line into tokens separated by matches of this regex, and treat each token as a symbol")
+            .long("separator")
+            .takes_value(true)
+            .value_name("REGEX")
+            .validator(|value| Regex::new(&value).map(|_| ()).map_err(|err| err.to_string()))
+            .conflicts_with("SYMBOLS"),
+    ]
+}
+
+/// The options of subcommands which count every line, selecting how the input is read and parsed
+fn parse_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("STREAM")
+            .help("Stream the input file in batches instead of reading it all at once. Input from stdin is always streamed")
+            .long("stream"),
+        Arg::with_name("LENIENT")
+            .help("Skip invalid lines instead of failing, and count only the valid ones")
+            .long("lenient")
+            .short("l"),
+        Arg::with_name("REJECTS")
+            .help("Write skipped lines to this file, prefixed by their line numbers")
+            .long("rejects")
+            .takes_value(true)
+            .value_name("FILE")
+            .requires("LENIENT"),
+        Arg::with_name("BINARY")
+            .help("Treat the input as binary records, using all 256 byte values as symbols")
+            .long("binary")
+            .short("b")
+            .conflicts_with_all(&["LENIENT", "STREAM", "SYMBOLS", "SEPARATOR"]),
+        Arg::with_name("DELIMITER")
+            .help("The byte separating binary records: a single character, or a decimal or 0x-prefixed hex value [default: newline]")
+            .long("delimiter")
+            .takes_value(true)
+            .value_name("BYTE")
+            .validator(|value| parse_delimiter(&value).map(|_| ()))
+            .requires("BINARY"),
+        Arg::with_name("WIDTH")
+            .help("Split binary input into fixed-width records of this many bytes")
+            .long("width")
+            .takes_value(true)
+            .value_name("BYTES")
+            .validator(|value| {
+                value
+                    .parse::<NonZeroUsize>()
+                    .map(|_| ())
+                    .map_err(|_| "width must be a positive integer".to_string())
+            })
+            .requires("BINARY")
+            .conflicts_with("DELIMITER"),
+    ]
+}
+
+/// The options of subcommands which can select pattern classes by size
+fn size_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("MIN_SIZE")
+            .help("Select only pattern classes with at least K members, whose strings each have at least K - 1 friends [default: 2]")
+            .long("min-size")
+            .takes_value(true)
+            .value_name("K")
+            .validator(parse_size),
+        Arg::with_name("MAX_SIZE")
+            .help("Select only pattern classes with at most K members")
+            .long("max-size")
+            .takes_value(true)
+            .value_name("K")
+            .validator(parse_size),
+    ]
+}
+
+/// The output format option of subcommands other than annotate
+fn format_arg() -> Arg<'static, 'static> {
+    Arg::with_name("FORMAT")
+        .help("The output format: plain text, tab-separated with a header row, or a versioned JSON document which also contains the input's metadata and the time taken by each phase. With json, errors are also written as a JSON document")
+        .long("format")
+        .takes_value(true)
+        .possible_values(&["text", "tsv", "json"])
+        .default_value("text")
+}
+
+/// Print an error to stderr, listing each invalid line separately
+fn report(err: &PatternError) {
+    match err {
+        PatternError::InvalidLines(errors) => {
+            errors.iter().for_each(|err| eprintln!("{}", err));
+            eprintln!("Error: {}", err);
+        }
+        _ => eprintln!("Error: {}", err),
+    }
+}
+
+/// Print an error to stdout as a versioned JSON document, listing each invalid line or query separately
+fn report_json(command: &str, err: &PatternError) {
+    // each invalid line or query, numbered under `key` and described as a `kind`
+    let located = |key, kind, errors: &[PatternError]| {
+        let errors = errors
+            .iter()
+            .map(|err| {
+                Json::Object(vec![
+                    (key, err.line().into()),
+                    ("message", err.describe(kind).into()),
+                ])
+            })
+            .collect();
+        Json::Array(errors)
+    };
+    let mut error = vec![("message", err.to_string().into())];
+    match err {
+        PatternError::InvalidLines(errors) => {
+            error.push(("lines", located("line", "Line", errors)))
+        }
+        PatternError::InvalidQueries(errors) => {
+            error.push(("queries", located("query", "Query", errors)))
+        }
+        _ => (),
+    }
+    let document = Json::Object(vec![
+        ("version", JSON_VERSION.into()),
+        ("command", command.into()),
+        ("error", Json::Object(error)),
+    ]);
+    println!("{}", document);
+}
+
+/// Parse a delimiter byte from a single character, or a decimal or hex value
+fn parse_delimiter(value: &str) -> Result<u8, String> {
+    let parsed = if let Some(hex) = value.strip_prefix("0x") {
+        u8::from_str_radix(hex, 16).ok()
+    } else if value.len() == 1 {
+        Some(value.as_bytes()[0])
+    } else {
+        value.parse::<u8>().ok()
+    };
+    parsed.ok_or_else(|| format!("{} isn't a single character or a byte value", value))
+}
+
+/// Check that a class size is a positive integer
+fn parse_size(value: String) -> Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(size) if size > 0 => Ok(()),
+        _ => Err("class sizes must be positive integers".to_string()),
+    }
+}
+
+/// Get the class size threshold, if either bound was given
+///
+/// Exits with a usage error if the maximum is below the minimum, since no class could be selected
+fn threshold(params: &ArgMatches) -> Option<Threshold> {
+    let min = params.value_of("MIN_SIZE").map(|min| min.parse().unwrap());
+    let max = params.value_of("MAX_SIZE").map(|max| max.parse().unwrap());
+    let threshold = (min.is_some() || max.is_some()).then(|| Threshold {
+        min: min.unwrap_or(Threshold::default().min),
+        max,
+    });
+    if let Some(Threshold {
+        min,
+        max: Some(max),
+    }) = threshold
+    {
+        if max < min {
+            clap::Error::with_description(
+                &format!(
+                    "--max-size {} is below the minimum class size of {}",
+                    max, min
+                ),
+                clap::ErrorKind::ArgumentConflict,
+            )
+            .exit();
+        }
+    }
+    threshold
+}
+
+/// Get the counting strategy. By default, the count is sharded if there's more than one thread to share it
+fn strategy(params: &ArgMatches) -> Strategy {
+    match params.value_of("STRATEGY") {
+        Some("hash") => Strategy::Hash,
+        Some("sharded") => Strategy::Sharded,
+        Some("packed") => Strategy::Packed,
+        Some("sort") => Strategy::Sort,
+        _ if rayon::current_num_threads() > 1 => Strategy::Sharded,
+        _ => Strategy::Hash,
+    }
+}
+
+/// Read all of the input, from stdin if the path is "-"
+fn read_input(path: &str) -> io::Result<Vec<u8>> {
+    if path == "-" {
+        let mut data = vec![];
+        io::stdin().read_to_end(&mut data)?;
+        Ok(data)
+    } else {
+        fs::read(path)
+    }
+}
+
+/// Open the input for streaming, from stdin if the path is "-"
+fn open_input(path: &str) -> io::Result<Box<dyn BufRead + Send>> {
+    if path == "-" {
+        Ok(Box::new(BufReader::new(io::stdin())))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+/// Write any rejected lines to the rejects file if one was requested, and summarise them
+fn handle_rejects(
+    params: &ArgMatches,
+    rejected: &[RejectedLine],
+    lines: usize,
+) -> Result<(), PatternError> {
+    let mut rejects = Rejects::create(params)?;
+    for reject in rejected {
+        rejects.write(reject)?;
+    }
+    rejects.finish()?;
+    summarise_rejects(rejected.len(), lines);
+    Ok(())
+}
+
+/// Report how many lines were skipped, if any were
+fn summarise_rejects(rejected: usize, lines: usize) {
+    if rejected > 0 {
+        eprintln!("Skipped {} invalid line(s) out of {}", rejected, lines);
+    }
+}
+
+/// The rejects file, if one was requested, which rejected lines are written to as they're found
+struct Rejects {
+    writer: Option<BufWriter<File>>,
+}
+
+impl Rejects {
+    fn create(params: &ArgMatches) -> Result<Self, PatternError> {
+        let writer = match params.value_of("REJECTS") {
+            Some(path) => Some(BufWriter::new(File::create(path)?)),
+            None => None,
+        };
+        Ok(Rejects { writer })
+    }
+
+    /// Write a rejected line as "line number<TAB>contents"
+    fn write(&mut self, reject: &RejectedLine) -> Result<(), PatternError> {
+        if let Some(writer) = &mut self.writer {
+            write!(writer, "{}\t", reject.line)?;
+            writer.write_all(&reject.contents)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), PatternError> {
+        if let Some(mut writer) = self.writer {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// What happens to the rejected lines of a stream: under --lenient, each is written to the
+/// rejects file as soon as it's found, and otherwise its error is kept to be reported
+enum StreamRejects {
+    Lenient(Rejects),
+    Strict(Vec<PatternError>),
+}
+
+impl StreamRejects {
+    fn new(params: &ArgMatches) -> Result<Self, PatternError> {
+        Ok(if params.is_present("LENIENT") {
+            StreamRejects::Lenient(Rejects::create(params)?)
+        } else {
+            StreamRejects::Strict(vec![])
+        })
+    }
+
+    fn reject(&mut self, reject: RejectedLine) -> Result<(), PatternError> {
+        match self {
+            StreamRejects::Lenient(rejects) => rejects.write(&reject),
+            StreamRejects::Strict(errors) => {
+                errors.push(reject.error);
+                Ok(())
+            }
+        }
+    }
+
+    /// Finish with a stream of `lines` lines, of which those numbered in `rejected` were rejected
+    fn finish(self, run: &mut Run, rejected: Vec<usize>, lines: usize) -> Result<(), PatternError> {
+        match self {
+            StreamRejects::Lenient(rejects) => {
+                rejects.finish()?;
+                summarise_rejects(rejected.len(), lines);
+                run.rejected = rejected;
+                Ok(())
+            }
+            StreamRejects::Strict(errors) if errors.is_empty() => Ok(()),
+            StreamRejects::Strict(errors) => Err(PatternError::InvalidLines(errors)),
+        }
+    }
+}
+
+/// Get the binary record format
+fn records(params: &ArgMatches) -> Records {
+    match params.value_of("WIDTH") {
+        Some(width) => Records::Fixed(width.parse().unwrap()),
+        None => Records::Delimited(
+            params
+                .value_of("DELIMITER")
+                .map_or(Ok(b'\n'), parse_delimiter)
+                .unwrap(),
+        ),
+    }
+}
+
+/// The version of the JSON output's structure, which is incremented whenever a field changes or is removed
+const JSON_VERSION: u64 = 1;
+
+/// The output format shared by most subcommands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    Tsv,
+    Json,
+}
+
+/// Get the output format
+fn format(params: &ArgMatches) -> Format {
+    match params.value_of("FORMAT") {
+        Some("json") => Format::Json,
+        Some("tsv") => Format::Tsv,
+        _ => Format::Text,
+    }
+}
+
+/// A JSON value
+enum Json {
+    Null,
+    Bool(bool),
+    Int(u64),
+    Float(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl From<u32> for Json {
+    fn from(value: u32) -> Self {
+        Json::Int(value.into())
+    }
+}
+
+impl From<u64> for Json {
+    fn from(value: u64) -> Self {
+        Json::Int(value)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(value: usize) -> Self {
+        Json::Int(value as u64)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::Str(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::Str(value)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Int(value) => write!(f, "{}", value),
+            Json::Float(value) => write!(f, "{}", value),
+            Json::Str(value) => write!(f, "{}", json_string(value)),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (idx, (key, value)) in fields.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", json_string(key), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// A line and its contents, as a JSON object
+fn json_line(line: usize, string: &str) -> Json {
+    Json::Object(vec![("line", line.into()), ("string", string.into())])
+}
+
+/// An annotated line, as a JSON object
+fn annotation_json(annotation: &Annotation) -> Json {
+    Json::Object(vec![
+        ("line", annotation.line.into()),
+        ("string", annotation.string.into()),
+        ("pattern", annotation.pattern.to_string().into()),
+        ("class", annotation.class.into()),
+        ("friends", annotation.friends.into()),
+    ])
+}
+
+/// The input, and the time taken by each phase, of a single subcommand
+struct Run<'a> {
+    command: &'a str,
+    /// The input file, or "-" for stdin, if the subcommand reads input
+    path: Option<&'a str>,
+    /// The number of lines (or binary records), if they were all counted
+    lines: Option<usize>,
+    /// The line numbers of any lines skipped by --lenient
+    rejected: Vec<usize>,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl<'a> Run<'a> {
+    fn new(command: &'a str, path: Option<&'a str>) -> Self {
+        Run {
+            command,
+            path,
+            lines: None,
+            rejected: vec![],
+            phases: vec![],
+        }
+    }
+
+    /// Run a phase, and record how long it took
+    fn time<T, F>(&mut self, phase: &'static str, f: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        let start = Instant::now();
+        let result = f();
+        self.phases.push((phase, start.elapsed()));
+        result
+    }
+
+    /// Record the rejected lines of a lenient run
+    fn reject(&mut self, rejected: &[RejectedLine]) {
+        self.rejected = rejected.iter().map(|reject| reject.line).collect();
+    }
+
+    /// Write a versioned JSON document containing the input metadata, timings, and a result
+    fn write_json(&self, result: Json) -> Result<(), PatternError> {
+        let document = Json::Object(vec![
+            ("version", JSON_VERSION.into()),
+            ("command", self.command.into()),
+            (
+                "input",
+                Json::Object(vec![
+                    ("path", self.path.into()),
+                    ("lines", self.lines.into()),
+                    (
+                        "rejected",
+                        Json::Array(self.rejected.iter().map(|&line| line.into()).collect()),
+                    ),
+                ]),
+            ),
+            (
+                "timings",
+                Json::Array(
+                    self.phases
+                        .iter()
+                        .map(|(phase, elapsed)| {
+                            Json::Object(vec![
+                                ("phase", (*phase).into()),
+                                ("ms", Json::Float(elapsed.as_secs_f64() * 1000.0)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+            ("result", result),
+        ]);
+        let mut writer = stdout_writer();
+        writeln!(writer, "{}", document)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// A buffered writer to stdout, for output which may run to many lines
+fn stdout_writer() -> BufWriter<io::StdoutLock<'static>> {
+    BufWriter::new(io::stdout().lock())
+}
+
+/// Escape tabs, line breaks, and backslashes, so that a field can't break a TSV row
+fn tsv_field(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+/// Quote a CSV field if it contains a comma, quote, or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Quote and escape a JSON string
+fn json_string(string: &str) -> String {
+    let mut quoted = String::with_capacity(string.len() + 2);
+    quoted.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c < ' ' => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Count friendly strings, or the strings in classes of the selected sizes
+fn count<S>(
+    params: &ArgMatches,
+    input_file: &str,
+    symbols: Symbols,
+    run: &mut Run,
+) -> Result<(), PatternError>
+where
+    S: BuildHasher + Default + Send + Sync,
+{
+    let duplicates = if params.is_present("DISTINCT") {
+        Duplicates::Distinct
+    } else if params.is_present("EXCLUDE_IDENTICAL") {
+        Duplicates::NotFriends
+    } else {
+        Duplicates::Friends
+    };
+    let limits = threshold(params);
+    let lenient = params.is_present("LENIENT");
+    let strategy = strategy(params);
+    let threshold = limits.unwrap_or_default();
     // count "friendly" patterns
-    let friendly = count_frequency(&strings);
-    println!("Number of friendly strings: {:?}", friendly);
+    let counted = if duplicates != Duplicates::Friends {
+        // identical lines can only be recognised by keeping every line
+        let data = run.time("read", || read_input(input_file))?;
+        let lines = run.time("split", || bytes_to_lines(&data))?;
+        run.lines = Some(lines.len());
+        let friendly = run.time("count", || {
+            count_strings_with_hasher::<S>(&lines, symbols, duplicates)
+        })?;
+        ClassCount {
+            strings: friendly,
+            classes: 0,
+        }
+    } else if params.is_present("BINARY") {
+        let data = run.time("read", || read_input(input_file))?;
+        let patterns = run.time("parse", || bytes_to_patterns(&data, records(params)));
+        run.lines = Some(patterns.len());
+        run.time("count", || {
+            count_classes_with_hasher::<S, _>(&patterns, strategy, &threshold)
+        })
+    } else if input_file == "-" || params.is_present("STREAM") {
+        let reader = open_input(input_file)?;
+        let mut rejects = StreamRejects::new(params)?;
+        let counted = run.time("stream", || {
+            count_reader_classes_with_hasher::<S, _, _>(reader, symbols, &threshold, |reject| {
+                rejects.reject(reject)
+            })
+        })?;
+        run.lines = Some(counted.lines);
+        rejects.finish(run, counted.rejected, counted.lines)?;
+        ClassCount {
+            strings: counted.friendly,
+            classes: counted.classes,
+        }
+    } else if lenient {
+        let parsed = run.time("parse", || {
+            file_to_patterns_lenient_with_hasher::<S, _>(input_file, symbols)
+        })?;
+        let lines = parsed.rejected.len() + parsed.patterns.len();
+        run.lines = Some(lines);
+        handle_rejects(params, &parsed.rejected, lines)?;
+        run.reject(&parsed.rejected);
+        run.time("count", || {
+            count_classes_with_hasher::<S, _>(&parsed.patterns, strategy, &threshold)
+        })
+    } else {
+        let patterns = run.time("parse", || {
+            file_to_pattern_set_with_hasher::<S, _>(input_file, symbols)
+        })?;
+        run.lines = Some(patterns.len());
+        run.time("count", || {
+            count_classes_with_hasher::<S, _>(&patterns, strategy, &threshold)
+        })
+    };
+    match (format(params), limits) {
+        (Format::Text, Some(_)) => println!(
+            "Number of strings in matching classes: {:?}\nNumber of matching classes: {:?}",
+            counted.strings, counted.classes
+        ),
+        (Format::Text, None) => println!("Number of friendly strings: {:?}", counted.strings),
+        (Format::Tsv, Some(_)) => {
+            println!("strings\tclasses\n{}\t{}", counted.strings, counted.classes)
+        }
+        (Format::Tsv, None) => println!("friendly_strings\n{}", counted.strings),
+        (Format::Json, Some(limits)) => run.write_json(Json::Object(vec![
+            ("strings", counted.strings.into()),
+            ("classes", counted.classes.into()),
+            ("min_size", limits.min.into()),
+            ("max_size", limits.max.into()),
+        ]))?,
+        (Format::Json, None) => run.write_json(Json::Object(vec![(
+            "friendly_strings",
+            counted.strings.into(),
+        )]))?,
+    }
+    Ok(())
+}
+
+/// Print each friend group, with its pattern and the line number and contents of each member
+fn groups<S>(
+    params: &ArgMatches,
+    input_file: &str,
+    symbols: Symbols,
+    run: &mut Run,
+) -> Result<(), PatternError>
+where
+    S: BuildHasher + Default + Send + Sync,
+{
+    let data = run.time("read", || read_input(input_file))?;
+    let lines = run.time("split", || bytes_to_lines(&data))?;
+    run.lines = Some(lines.len());
+    let mut groups = run.time("group", || friend_groups_with_hasher::<S>(&lines, symbols))?;
+    if let Some(limits) = threshold(params) {
+        groups.retain(|group| limits.contains(group.members.len() as u32));
+    }
+    let format = format(params);
+    if format == Format::Json {
+        let result = Json::Object(vec![
+            ("group_count", groups.len().into()),
+            ("friendly_strings", groups.friendly_count().into()),
+            (
+                "groups",
+                Json::Array(
+                    groups
+                        .iter()
+                        .map(|group| {
+                            Json::Object(vec![
+                                ("pattern", group.pattern.to_string().into()),
+                                (
+                                    "members",
+                                    Json::Array(
+                                        group
+                                            .members
+                                            .iter()
+                                            .map(|member| json_line(member.line, member.string))
+                                            .collect(),
+                                    ),
+                                ),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+        ]);
+        return run.write_json(result);
+    }
+    let mut writer = stdout_writer();
+    if format == Format::Tsv {
+        writeln!(writer, "group\tpattern\tline\tstring")?;
+    }
+    for (idx, group) in groups.iter().enumerate() {
+        match format {
+            Format::Tsv => {
+                let pattern = group.pattern.to_string();
+                for member in &group.members {
+                    writeln!(
+                        writer,
+                        "{}\t{}\t{}\t{}",
+                        idx,
+                        pattern,
+                        member.line,
+                        tsv_field(member.string)
+                    )?;
+                }
+            }
+            _ => {
+                writeln!(writer, "{}", group.pattern)?;
+                for member in &group.members {
+                    writeln!(writer, "\t{}\t{}", member.line, member.string)?;
+                }
+            }
+        }
+    }
+    if format == Format::Text {
+        writeln!(
+            writer,
+            "Number of friend groups: {:?}\nNumber of friendly strings: {:?}",
+            groups.len(),
+            groups.friendly_count()
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Print each input line with its pattern, class id, and number of friends
+fn annotate<S>(
+    params: &ArgMatches,
+    input_file: &str,
+    symbols: Symbols,
+    run: &mut Run,
+) -> Result<(), PatternError>
+where
+    S: BuildHasher + Default + Send + Sync,
+{
+    let data = run.time("read", || read_input(input_file))?;
+    let lines = run.time("split", || bytes_to_lines(&data))?;
+    run.lines = Some(lines.len());
+    let annotations = run.time("annotate", || annotate_with_hasher::<S>(&lines, symbols))?;
+    let format = params.value_of("FORMAT").unwrap();
+    if format == "json" {
+        let rows = annotations.iter().map(annotation_json).collect();
+        return run.write_json(Json::Object(vec![("rows", Json::Array(rows))]));
+    }
+    let mut writer = stdout_writer();
+    match format {
+        "tsv" => writeln!(writer, "line\tstring\tpattern\tclass\tfriends")?,
+        "csv" => writeln!(writer, "line,string,pattern,class,friends")?,
+        _ => (),
+    }
+    for annotation in &annotations {
+        let pattern = annotation.pattern.to_string();
+        match format {
+            "tsv" => writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}",
+                annotation.line,
+                tsv_field(annotation.string),
+                pattern,
+                annotation.class,
+                annotation.friends
+            )?,
+            "csv" => writeln!(
+                writer,
+                "{},{},{},{},{}",
+                annotation.line,
+                csv_field(annotation.string),
+                csv_field(&pattern),
+                annotation.class,
+                annotation.friends
+            )?,
+            _ => writeln!(writer, "{}", annotation_json(annotation))?,
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Print each query string's pattern, followed by the input lines which share it and their bijections
+fn query<S>(
+    params: &ArgMatches,
+    input_file: &str,
+    symbols: Symbols,
+    run: &mut Run,
+) -> Result<(), PatternError>
+where
+    S: BuildHasher + Default + Send + Sync,
+{
+    let queries: Vec<&str> = params.values_of("QUERIES").unwrap().collect();
+    let data = run.time("read", || read_input(input_file))?;
+    let lines = run.time("split", || bytes_to_lines(&data))?;
+    run.lines = Some(lines.len());
+    let results = match params.value_of("INDEX") {
+        Some(path) => {
+            let (index, origin) = run.time("load", || {
+                PatternSet::read_from(BufReader::new(File::open(path)?))
+            })?;
+            origin.check(&lines, &symbols)?;
+            run.time("match", || {
+                query_index_with_hasher::<S, _>(&lines, &index, &queries, symbols)
+            })?
+        }
+        None => run.time("match", || {
+            query_friends_with_hasher::<S>(&lines, &queries, symbols)
+        })?,
+    };
+    let format = format(params);
+    if format == Format::Json {
+        let result = results
+            .iter()
+            .map(|result| {
+                let friends = result
+                    .friends
+                    .iter()
+                    .map(|friend| {
+                        let bijection = friend
+                            .bijection
+                            .iter()
+                            .map(|&(from, to)| Json::Array(vec![from.into(), to.into()]))
+                            .collect();
+                        Json::Object(vec![
+                            ("line", friend.line.into()),
+                            ("string", friend.string.into()),
+                            ("bijection", Json::Array(bijection)),
+                        ])
+                    })
+                    .collect();
+                Json::Object(vec![
+                    ("query", result.query.into()),
+                    ("pattern", result.pattern.to_string().into()),
+                    ("matches", Json::Array(friends)),
+                ])
+            })
+            .collect();
+        return run.write_json(Json::Object(vec![("queries", Json::Array(result))]));
+    }
+    let mut writer = stdout_writer();
+    if format == Format::Tsv {
+        writeln!(writer, "query\tpattern\tline\tstring\tbijection")?;
+    }
+    for result in &results {
+        if format == Format::Text {
+            writeln!(writer, "{}\t{}", result.query, result.pattern)?;
+        }
+        for friend in &result.friends {
+            let bijection = bijection_text(&friend.bijection);
+            match format {
+                Format::Tsv => writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}\t{}",
+                    tsv_field(result.query),
+                    result.pattern,
+                    friend.line,
+                    tsv_field(friend.string),
+                    tsv_field(&bijection)
+                )?,
+                _ => writeln!(
+                    writer,
+                    "\t{}\t{}\t{}",
+                    friend.line, friend.string, bijection
+                )?,
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write a bijection as space-separated "from→to" pairs
+fn bijection_text(bijection: &[(&str, &str)]) -> String {
+    bijection
+        .iter()
+        .map(|(from, to)| format!("{}→{}", from, to))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Generate the patterns of every line, and write them to an index file which query can read
+fn index<S>(
+    params: &ArgMatches,
+    input_file: &str,
+    symbols: Symbols,
+    run: &mut Run,
+) -> Result<(), PatternError>
+where
+    S: BuildHasher + Default,
+{
+    let data = run.time("read", || read_input(input_file))?;
+    let lines = run.time("split", || bytes_to_lines(&data))?;
+    run.lines = Some(lines.len());
+    let origin = SetOrigin::new(&lines, &symbols);
+    let index: PatternSet = run
+        .time("parse", || {
+            strings_to_patterns_with_hasher::<S>(&lines, symbols)
+        })?
+        .into_iter()
+        .collect();
+    let output = params.value_of("OUTPUT").unwrap();
+    run.time("write", || {
+        index.write_to(&origin, BufWriter::new(File::create(output)?))
+    })?;
+    match format(params) {
+        Format::Text => println!("Indexed {} lines", index.len()),
+        Format::Tsv => println!("lines\n{}", index.len()),
+        Format::Json => run.write_json(Json::Object(vec![
+            ("lines", index.len().into()),
+            ("output", output.into()),
+        ]))?,
+    }
+    Ok(())
+}
+
+/// Print the bijection between two strings, or the reason they aren't friends
+fn explain(params: &ArgMatches, symbols: Symbols, run: &mut Run) -> Result<(), PatternError> {
+    let left = params.value_of("LEFT").unwrap();
+    let right = params.value_of("RIGHT").unwrap();
+    let friendly = run.time("explain", || are_friendly_with(left, right, symbols));
+    match format(params) {
+        Format::Text => match &friendly {
+            Ok(bijection) => println!("Friends: {}", bijection_text(bijection)),
+            Err(reason) => println!("Not friends: {}", reason),
+        },
+        Format::Tsv => match &friendly {
+            Ok(bijection) => println!(
+                "friendly\tbijection\treason\ntrue\t{}\t",
+                tsv_field(&bijection_text(bijection))
+            ),
+            Err(reason) => println!(
+                "friendly\tbijection\treason\nfalse\t\t{}",
+                tsv_field(&reason.to_string())
+            ),
+        },
+        Format::Json => {
+            let (bijection, reason) = match &friendly {
+                Ok(bijection) => {
+                    let pairs = bijection
+                        .iter()
+                        .map(|&(from, to)| Json::Array(vec![from.into(), to.into()]))
+                        .collect();
+                    (Json::Array(pairs), Json::Null)
+                }
+                Err(reason) => (Json::Null, unfriendly_json(reason)),
+            };
+            run.write_json(Json::Object(vec![
+                ("left", left.into()),
+                ("right", right.into()),
+                ("friendly", Json::Bool(friendly.is_ok())),
+                ("bijection", bijection),
+                ("reason", reason),
+            ]))?
+        }
+    }
+    Ok(())
+}
+
+/// The reason two strings aren't friends, as a JSON object
+fn unfriendly_json(reason: &Unfriendly) -> Json {
+    let mut fields = match *reason {
+        Unfriendly::Length { left, right } => vec![
+            ("kind", "length".into()),
+            ("left", left.into()),
+            ("right", right.into()),
+        ],
+        Unfriendly::Conflict {
+            position,
+            symbol,
+            mapped,
+            other,
+        } => vec![
+            ("kind", "conflict".into()),
+            ("position", position.into()),
+            ("symbol", symbol.into()),
+            ("mapped", mapped.into()),
+            ("other", other.into()),
+        ],
+        Unfriendly::Collision {
+            position,
+            symbol,
+            other,
+            mapped,
+        } => vec![
+            ("kind", "collision".into()),
+            ("position", position.into()),
+            ("symbol", symbol.into()),
+            ("other", other.into()),
+            ("mapped", mapped.into()),
+        ],
+    };
+    fields.push(("message", reason.to_string().into()));
+    Json::Object(fields)
+}
+
+/// Build a frequency report, reading the input in the same way as a count
+fn stats<S>(
+    params: &ArgMatches,
+    input_file: &str,
+    symbols: Symbols,
+    run: &mut Run,
+) -> Result<(), PatternError>
+where
+    S: BuildHasher + Default + Send + Sync,
+{
+    let lenient = params.is_present("LENIENT");
+    let report = if params.is_present("BINARY") {
+        let data = run.time("read", || read_input(input_file))?;
+        let patterns = run.time("parse", || bytes_to_patterns(&data, records(params)));
+        run.lines = Some(patterns.len());
+        run.time("count", || frequency_report_with_hasher::<S, _>(&patterns))
+    } else if input_file == "-" || params.is_present("STREAM") {
+        let reader = open_input(input_file)?;
+        let mut rejects = StreamRejects::new(params)?;
+        let (report, rejected) = run.time("stream", || {
+            report_reader_lenient_with_hasher::<S, _, _>(reader, symbols, |reject| {
+                rejects.reject(reject)
+            })
+        })?;
+        let lines = report.strings as usize + rejected.len();
+        run.lines = Some(lines);
+        rejects.finish(run, rejected, lines)?;
+        report
+    } else if lenient {
+        let parsed = run.time("parse", || {
+            file_to_patterns_lenient_with_hasher::<S, _>(input_file, symbols)
+        })?;
+        let lines = parsed.rejected.len() + parsed.patterns.len();
+        run.lines = Some(lines);
+        handle_rejects(params, &parsed.rejected, lines)?;
+        run.reject(&parsed.rejected);
+        run.time("count", || {
+            frequency_report_with_hasher::<S, _>(&parsed.patterns)
+        })
+    } else {
+        let patterns = run.time("parse", || {
+            file_to_pattern_set_with_hasher::<S, _>(input_file, symbols)
+        })?;
+        run.lines = Some(patterns.len());
+        run.time("count", || frequency_report_with_hasher::<S, _>(&patterns))
+    };
+    match format(params) {
+        Format::Text => print_report(&report),
+        Format::Tsv => {
+            let mut writer = stdout_writer();
+            writeln!(writer, "statistic\tvalue")?;
+            writeln!(writer, "strings\t{}", report.strings)?;
+            writeln!(writer, "distinct_patterns\t{}", report.distinct)?;
+            writeln!(writer, "friendly_strings\t{}", report.friendly)?;
+            writeln!(writer, "friend_pairs\t{}", report.friend_pairs)?;
+            writeln!(writer, "singletons\t{}", report.singletons)?;
+            if let Some(largest) = &report.largest {
+                writeln!(writer, "largest_class_size\t{}", largest.size)?;
+                writeln!(writer, "largest_class_pattern\t{}", largest.pattern)?;
+            }
+            for (size, classes) in &report.histogram {
+                writeln!(writer, "classes_of_size_{}\t{}", size, classes)?;
+            }
+            writer.flush()?;
+        }
+        Format::Json => {
+            let largest = report.largest.as_ref().map_or(Json::Null, |largest| {
+                Json::Object(vec![
+                    ("size", largest.size.into()),
+                    ("pattern", largest.pattern.to_string().into()),
+                ])
+            });
+            let histogram = report
+                .histogram
+                .iter()
+                .map(|(&size, &classes)| {
+                    Json::Object(vec![("size", size.into()), ("classes", classes.into())])
+                })
+                .collect();
+            run.write_json(Json::Object(vec![
+                ("strings", report.strings.into()),
+                ("distinct_patterns", report.distinct.into()),
+                ("friendly_strings", report.friendly.into()),
+                ("friend_pairs", report.friend_pairs.into()),
+                ("singletons", report.singletons.into()),
+                ("largest_class", largest),
+                ("histogram", Json::Array(histogram)),
+            ]))?
+        }
+    }
+    Ok(())
+}
+
+/// Print a frequency report, followed by its histogram as "size<TAB>number of classes" rows
+fn print_report(report: &FrequencyReport) {
+    println!("Number of strings: {:?}", report.strings);
+    println!("Number of distinct patterns: {:?}", report.distinct);
+    println!("Number of friendly strings: {:?}", report.friendly);
+    println!("Number of friend pairs: {:?}", report.friend_pairs);
+    println!("Number of singletons: {:?}", report.singletons);
+    if let Some(largest) = &report.largest {
+        println!(
+            "Largest class: {:?} members, pattern {}",
+            largest.size, largest.pattern
+        );
+    }
+    println!("Class sizes:");
+    for (size, classes) in &report.histogram {
+        println!("\t{}\t{}", size, classes);
+    }
+}
+
+/// Print the most common patterns, as "count<TAB>pattern<TAB>examples" rows
+fn top<S>(
+    params: &ArgMatches,
+    input_file: &str,
+    symbols: Symbols,
+    run: &mut Run,
+) -> Result<(), PatternError>
+where
+    S: BuildHasher + Default + Send + Sync,
+{
+    let n = params.value_of("NUMBER").unwrap().parse().unwrap();
+    let top = if input_file == "-" || params.is_present("STREAM") {
+        let capacity = params.value_of("CAPACITY").unwrap().parse().unwrap();
+        let reader = open_input(input_file)?;
+        let (top, lines) = run.time("stream", || {
+            top_patterns_reader_with_hasher::<S, _>(reader, symbols, n, capacity)
+        })?;
+        run.lines = Some(lines);
+        top
+    } else {
+        let data = run.time("read", || read_input(input_file))?;
+        let lines = run.time("split", || bytes_to_lines(&data))?;
+        run.lines = Some(lines.len());
+        run.time("count", || {
+            top_patterns_with_hasher::<S>(&lines, symbols, n)
+        })?
+    };
+    match format(params) {
+        Format::Json => {
+            let result = top
+                .iter()
+                .map(|entry| {
+                    Json::Object(vec![
+                        ("count", entry.count.into()),
+                        ("overestimate", entry.overestimate.into()),
+                        ("pattern", entry.pattern.to_string().into()),
+                        (
+                            "examples",
+                            Json::Array(
+                                entry
+                                    .examples
+                                    .iter()
+                                    .map(|example| example.as_str().into())
+                                    .collect(),
+                            ),
+                        ),
+                    ])
+                })
+                .collect();
+            return run.write_json(Json::Object(vec![("patterns", Json::Array(result))]));
+        }
+        Format::Tsv => {
+            let mut writer = stdout_writer();
+            write!(writer, "count\toverestimate\tpattern")?;
+            for idx in 1..=TOP_EXAMPLES {
+                write!(writer, "\texample_{}", idx)?;
+            }
+            writeln!(writer)?;
+            for entry in &top {
+                write!(
+                    writer,
+                    "{}\t{}\t{}",
+                    entry.count, entry.overestimate, entry.pattern
+                )?;
+                for idx in 0..TOP_EXAMPLES {
+                    let example = entry.examples.get(idx).map_or("", String::as_str);
+                    write!(writer, "\t{}", tsv_field(example))?;
+                }
+                writeln!(writer)?;
+            }
+            writer.flush()?;
+        }
+        Format::Text => {
+            let mut writer = stdout_writer();
+            for entry in &top {
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}",
+                    entry.count,
+                    entry.pattern,
+                    entry.examples.join("\t")
+                )?;
+            }
+            writer.flush()?;
+            if let Some(most) = top.iter().map(|entry| entry.overestimate).max() {
+                if most > 0 {
+                    eprintln!("Counts may be overestimated by up to {}", most);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print the lines whose classes meet the threshold or which have the given patterns, or with --invert, the other lines
+fn filter<S>(
+    params: &ArgMatches,
+    input_file: &str,
+    symbols: Symbols,
+    run: &mut Run,
+) -> Result<(), PatternError>
+where
+    S: BuildHasher + Default + Send + Sync,
+{
+    let threshold = threshold(params).unwrap_or_default();
+    let data = run.time("read", || read_input(input_file))?;
+    let lines = run.time("split", || bytes_to_lines(&data))?;
+    let patterns = run.time("parse", || {
+        strings_to_patterns_with_hasher::<S>(&lines, symbols)
+    })?;
+    run.lines = Some(lines.len());
+    let mask = match params.values_of("PATTERNS") {
+        Some(wanted) => {
+            let wanted: HashSet<Pattern, S> = wanted.map(|value| value.parse().unwrap()).collect();
+            run.time("match", || {
+                patterns
+                    .iter()
+                    .map(|pattern| wanted.contains(pattern.as_slice()))
+                    .collect()
+            })
+        }
+        None => run.time("count", || {
+            class_mask_with_hasher::<S, _>(&patterns, &threshold)
+        }),
+    };
+    let invert = params.is_present("INVERT");
+    let selected = lines
+        .iter()
+        .zip(&mask)
+        .enumerate()
+        .filter(|(_, (_, &matched))| matched != invert)
+        .map(|(idx, (line, _))| (idx + 1, line));
+    let format = format(params);
+    if format == Format::Json {
+        let result = selected
+            .map(|(line, string)| json_line(line, string))
+            .collect();
+        return run.write_json(Json::Object(vec![("lines", Json::Array(result))]));
+    }
+    let numbered = params.is_present("LINE_NUMBERS");
+    let mut writer = stdout_writer();
+    if format == Format::Tsv {
+        writeln!(writer, "line\tstring")?;
+    }
+    for (line, string) in selected {
+        match format {
+            Format::Tsv => writeln!(writer, "{}\t{}", line, tsv_field(string))?,
+            _ if numbered => writeln!(writer, "{}\t{}", line, string)?,
+            _ => writeln!(writer, "{}", string)?,
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn run<S>(command: &str, params: &ArgMatches) -> Result<(), PatternError>
+where
+    S: BuildHasher + Default + Send + Sync,
+{
+    let symbols = match (params.value_of("SEPARATOR"), params.value_of("SYMBOLS")) {
+        (Some(separator), _) => Symbols::Tokens(Regex::new(separator).unwrap()),
+        (None, Some("chars")) => Symbols::Chars,
+        (None, Some("graphemes")) => Symbols::Graphemes,
+        (None, Some("words")) => Symbols::Words,
+        _ => Symbols::Ascii,
+    };
+    if command == "explain" {
+        return explain(params, symbols, &mut Run::new(command, None));
+    }
+    let input_file = params.value_of("INPUT_STRINGS").unwrap();
+    let mut run = Run::new(command, Some(input_file));
+    match command {
+        "groups" => groups::<S>(params, input_file, symbols, &mut run),
+        "annotate" => annotate::<S>(params, input_file, symbols, &mut run),
+        "query" => query::<S>(params, input_file, symbols, &mut run),
+        "index" => index::<S>(params, input_file, symbols, &mut run),
+        "stats" => stats::<S>(params, input_file, symbols, &mut run),
+        "top" => top::<S>(params, input_file, symbols, &mut run),
+        "filter" => filter::<S>(params, input_file, symbols, &mut run),
+        _ => count::<S>(params, input_file, symbols, &mut run),
+    }
 }
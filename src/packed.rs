@@ -0,0 +1,75 @@
+/// A pattern packed into a single integer
+///
+/// Fixed-width integers are much cheaper to hash and compare than slices,
+/// and most patterns of up to a few dozen symbols fit into a `u64` or `u128`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PackedPattern {
+    U64(u64),
+    U128(u128),
+}
+
+// the pattern's length is packed into the lowest digit of the key, so that patterns of different
+// lengths whose other digits happen to produce the same value can't collide
+const LENGTH_LIMIT: u128 = 128;
+
+/// Attempt to pack a pattern into an integer
+///
+/// Each index in a pattern is at most one more than the largest index before it, so it's packed
+/// as a single digit whose base is the number of values it could have taken: the first index
+/// needs no space at all, and a pattern with few distinct symbols needs very little.
+/// Returns `None` if the pattern is too long, or if it doesn't fit into a `u128`, or if it isn't
+/// a valid pattern (i.e. it wasn't produced by one of this crate's generators).
+#[inline]
+pub fn pack_pattern(pattern: &[u8]) -> Option<PackedPattern> {
+    if pattern.len() as u128 >= LENGTH_LIMIT {
+        return None;
+    }
+    // the first index is the least significant digit, so that the base of each digit is
+    // known by the time it's decoded: this is what makes the packing injective
+    let mut key = pattern.len() as u64;
+    // the product of the bases so far: key is always less than this
+    let mut range = LENGTH_LIMIT as u64;
+    // the number of distinct indices seen so far, which is also the next new index
+    let mut distinct = 0u8;
+    // u64 arithmetic is much cheaper, so it's used for as long as the key fits
+    for (idx, &index) in pattern.iter().enumerate() {
+        if index > distinct {
+            return None;
+        }
+        let base = u64::from(distinct) + 1;
+        match range.checked_mul(base) {
+            Some(next) => {
+                key += u64::from(index) * range;
+                range = next;
+            }
+            None => return pack_wide(&pattern[idx..], key.into(), range.into(), distinct),
+        }
+        if index == distinct {
+            distinct += 1;
+        }
+    }
+    Some(PackedPattern::U64(key))
+}
+
+/// Continue packing the rest of a pattern which has outgrown a `u64`
+#[cold]
+fn pack_wide(
+    rest: &[u8],
+    mut key: u128,
+    mut range: u128,
+    mut distinct: u8,
+) -> Option<PackedPattern> {
+    for &index in rest {
+        if index > distinct {
+            return None;
+        }
+        let base = u128::from(distinct) + 1;
+        let weight = range;
+        range = range.checked_mul(base)?;
+        key += u128::from(index) * weight;
+        if index == distinct {
+            distinct += 1;
+        }
+    }
+    Some(PackedPattern::U128(key))
+}
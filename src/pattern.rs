@@ -0,0 +1,170 @@
+use crate::generic::{compare_patterns, pattern_indices, push_index, write_letters};
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// A pattern, which displays and parses in letter form
+///
+/// `"ABAB".parse::<Pattern>()` gives the pattern of "XYXY", and displaying it gives "ABAB" again:
+/// see `pattern_letters` for how indices past 26 are written. A `Pattern` holds the same bytes as
+/// the `Vec<u8>` returned by `generate_pattern`, so it hashes and compares equal in the same way,
+/// and a `HashMap` keyed by patterns can be looked up with a byte slice. Patterns are ordered by
+/// their indices rather than their bytes, so sorting them sorts their letter forms.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Pattern(Vec<u8>);
+
+impl Pattern {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    /// The symbol indices of the pattern
+    pub fn indices(&self) -> impl Iterator<Item = u32> + '_ {
+        pattern_indices(&self.0)
+    }
+}
+
+impl Ord for Pattern {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_patterns(&self.0, &other.0)
+    }
+}
+
+impl PartialOrd for Pattern {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The bytes aren't validated, so any pattern produced by this crate can be wrapped
+impl From<Vec<u8>> for Pattern {
+    fn from(pattern: Vec<u8>) -> Self {
+        Pattern(pattern)
+    }
+}
+
+impl From<&[u8]> for Pattern {
+    fn from(pattern: &[u8]) -> Self {
+        Pattern(pattern.to_vec())
+    }
+}
+
+impl From<Pattern> for Vec<u8> {
+    fn from(pattern: Pattern) -> Self {
+        pattern.0
+    }
+}
+
+impl AsRef<[u8]> for Pattern {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Borrow<[u8]> for Pattern {
+    fn borrow(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl PartialEq<[u8]> for Pattern {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<Vec<u8>> for Pattern {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        &self.0 == other
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_letters(&self.0, f)
+    }
+}
+
+/// Why a string isn't a pattern in letter form
+///
+/// Positions are 1-based, and count symbols, so a braced index is a single position.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ParsePatternError {
+    /// A char which isn't an ASCII letter, and doesn't start a braced index
+    InvalidChar { position: usize, found: char },
+    /// A braced index which isn't closed, isn't a number, or is below 52 and so should be a letter
+    InvalidIndex { position: usize },
+    /// An index more than one greater than every index before it, which no string can produce:
+    /// each new symbol gets the next index, so "AB" is a pattern but "BA" isn't
+    OutOfOrder { position: usize },
+}
+
+impl fmt::Display for ParsePatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParsePatternError::InvalidChar { position, found } => write!(
+                f,
+                "position {}: {:?} isn't a letter or a braced index",
+                position, found
+            ),
+            ParsePatternError::InvalidIndex { position } => {
+                write!(f, "position {}: invalid braced index", position)
+            }
+            ParsePatternError::OutOfOrder { position } => write!(
+                f,
+                "position {}: a new symbol must take the next unused letter",
+                position
+            ),
+        }
+    }
+}
+
+impl Error for ParsePatternError {}
+
+impl FromStr for Pattern {
+    type Err = ParsePatternError;
+
+    fn from_str(letters: &str) -> Result<Self, Self::Err> {
+        let mut pattern = Vec::with_capacity(letters.len());
+        // the index the next new symbol would get
+        let mut distinct = 0u32;
+        let mut rest = letters;
+        let mut position = 0;
+        while let Some(c) = rest.chars().next() {
+            position += 1;
+            let (index, len) = match c {
+                'A'..='Z' => (u32::from(c) - u32::from('A'), 1),
+                'a'..='z' => (u32::from(c) - u32::from('a') + 26, 1),
+                '{' => {
+                    let end = rest
+                        .find('}')
+                        .ok_or(ParsePatternError::InvalidIndex { position })?;
+                    let digits = &rest[1..end];
+                    // only the form pattern_letters writes is accepted, so that parsing round-trips
+                    let canonical = digits.bytes().all(|byte| byte.is_ascii_digit())
+                        && !digits.starts_with('0');
+                    match digits.parse::<u32>() {
+                        Ok(index) if canonical && index > 51 => (index, end + 1),
+                        _ => return Err(ParsePatternError::InvalidIndex { position }),
+                    }
+                }
+                found => return Err(ParsePatternError::InvalidChar { position, found }),
+            };
+            if index > distinct {
+                return Err(ParsePatternError::OutOfOrder { position });
+            }
+            if index == distinct {
+                distinct += 1;
+            }
+            push_index(&mut pattern, index);
+            rest = &rest[len..];
+        }
+        Ok(Pattern(pattern))
+    }
+}
@@ -0,0 +1,151 @@
+use crate::friendly::bijection;
+use crate::{checked_patterns, Pattern, PatternError, PatternSource, Symbols, TrustedHasher};
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A string in a corpus which is a friend of a query string
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Friend<'a> {
+    /// The 1-based position of the string in the corpus
+    pub line: usize,
+    pub string: &'a str,
+    /// Each symbol of the query string, paired with the symbol of this string which it maps to,
+    /// in order of first appearance
+    pub bijection: Vec<(&'a str, &'a str)>,
+}
+
+/// A query string, its pattern, and its friends in a corpus
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QueryResult<'a> {
+    pub query: &'a str,
+    pub pattern: Pattern,
+    /// Friends in corpus order. A corpus string identical to the query is included
+    pub friends: Vec<Friend<'a>>,
+}
+
+/// Find the friends of each query string among the strings of a corpus
+pub fn query_friends<'a>(
+    strings: &[&'a str],
+    queries: &[&'a str],
+    symbols: Symbols,
+) -> Result<Vec<QueryResult<'a>>, PatternError> {
+    query_friends_with_hasher::<TrustedHasher>(strings, queries, symbols)
+}
+
+/// Find the friends of each query string among the strings of a corpus, using the given hasher
+pub fn query_friends_with_hasher<'a, S>(
+    strings: &[&'a str],
+    queries: &[&'a str],
+    symbols: Symbols,
+) -> Result<Vec<QueryResult<'a>>, PatternError>
+where
+    S: BuildHasher + Default,
+{
+    let patterns = checked_patterns::<S, _>(strings, &symbols)?;
+    query_index_with_hasher::<S, _>(strings, &patterns, queries, symbols)
+}
+
+/// Find the friends of each query string in a corpus whose patterns have already been generated
+///
+/// `index` holds the pattern of each corpus string, in order: it could be a `PatternSet` read
+/// with `PatternSet::read_from`, so that a large corpus only has to be parsed once, and whose
+/// origin can be checked with `SetOrigin::check`. It must have been generated from the same
+/// strings using the same symbols: a `PatternError::IndexMismatch` is returned if it doesn't have
+/// one pattern per string, or if it pairs a query with a string which isn't its friend.
+pub fn query_index<'a, P>(
+    strings: &[&'a str],
+    index: &P,
+    queries: &[&'a str],
+    symbols: Symbols,
+) -> Result<Vec<QueryResult<'a>>, PatternError>
+where
+    P: PatternSource + ?Sized,
+{
+    query_index_with_hasher::<TrustedHasher, P>(strings, index, queries, symbols)
+}
+
+/// Find the friends of each query string in a corpus with an index, using the given hasher
+pub fn query_index_with_hasher<'a, S, P>(
+    strings: &[&'a str],
+    index: &P,
+    queries: &[&'a str],
+    symbols: Symbols,
+) -> Result<Vec<QueryResult<'a>>, PatternError>
+where
+    S: BuildHasher + Default,
+    P: PatternSource + ?Sized,
+{
+    if index.pattern_count() != strings.len() {
+        return Err(PatternError::IndexMismatch(format!(
+            "the index has {} patterns, but the corpus has {} strings",
+            index.pattern_count(),
+            strings.len()
+        )));
+    }
+    // queries are numbered from 1, as if they were lines
+    let patterns = checked_patterns::<S, _>(queries, &symbols).map_err(|err| match err {
+        PatternError::InvalidLines(errors) => PatternError::InvalidQueries(errors),
+        other => other,
+    })?;
+    // the corpus positions of each queried pattern's members
+    let mut matches: HashMap<&[u8], Vec<usize>, S> =
+        HashMap::with_capacity_and_hasher(patterns.len(), S::default());
+    patterns.iter().for_each(|pattern| {
+        matches.entry(pattern).or_default();
+    });
+    (0..index.pattern_count()).for_each(|idx| {
+        if let Some(members) = matches.get_mut(index.pattern(idx)) {
+            members.push(idx);
+        }
+    });
+    queries
+        .iter()
+        .zip(&patterns)
+        .map(|(&query, pattern)| {
+            let from = split_symbols(query, &symbols);
+            let friends = matches[pattern.as_slice()]
+                .iter()
+                .map(|&idx| {
+                    let to = split_symbols(strings[idx], &symbols);
+                    // friends' symbols always pair up one-to-one, unless the index is for other strings
+                    let bijection = bijection(&from, &to).map_err(|reason| {
+                        PatternError::IndexMismatch(format!(
+                            "the index matches line {} with {:?}, but {}",
+                            idx + 1,
+                            query,
+                            reason
+                        ))
+                    })?;
+                    Ok(Friend {
+                        line: idx + 1,
+                        string: strings[idx],
+                        bijection,
+                    })
+                })
+                .collect::<Result<_, PatternError>>()?;
+            Ok(QueryResult {
+                query,
+                pattern: pattern.as_slice().into(),
+                friends,
+            })
+        })
+        .collect()
+}
+
+/// Split a string into the symbols its pattern is generated from
+pub(crate) fn split_symbols<'a>(string: &'a str, symbols: &Symbols) -> Vec<&'a str> {
+    match symbols {
+        // an ASCII string's chars are its bytes
+        Symbols::Ascii | Symbols::Chars => string
+            .char_indices()
+            .map(|(idx, c)| &string[idx..idx + c.len_utf8()])
+            .collect(),
+        Symbols::Graphemes => string.graphemes(true).collect(),
+        Symbols::Words => string.split_whitespace().collect(),
+        Symbols::Tokens(separator) => separator
+            .split(string)
+            .filter(|token| !token.is_empty())
+            .collect(),
+    }
+}
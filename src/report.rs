@@ -0,0 +1,86 @@
+use crate::generic::compare_patterns;
+use crate::{Pattern, PatternSource, TrustedHasher};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+
+/// The largest pattern class in a collection of patterns
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LargestClass {
+    pub pattern: Pattern,
+    /// The number of members
+    pub size: u32,
+}
+
+/// Summary statistics of the pattern classes in a collection of patterns
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FrequencyReport {
+    /// The number of strings
+    pub strings: u32,
+    /// The number of distinct patterns, i.e. the number of classes
+    pub distinct: u32,
+    /// The number of friendly strings: this is the value returned by `count_frequency`
+    pub friendly: u32,
+    /// The number of unordered pairs of friends, summing n(n - 1) / 2 over classes of n members
+    pub friend_pairs: u64,
+    /// The number of strings which have no friends
+    pub singletons: u32,
+    /// The largest class, or `None` if there are no patterns. Ties go to the lowest pattern
+    pub largest: Option<LargestClass>,
+    /// The number of classes of each size, keyed by size
+    pub histogram: BTreeMap<u32, u32>,
+}
+
+impl FrequencyReport {
+    /// Build a report from the size of each class
+    pub(crate) fn from_classes<'a, I>(classes: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a [u8], u32)>,
+    {
+        let mut report = FrequencyReport::default();
+        let mut largest: Option<(&[u8], u32)> = None;
+        for (pattern, size) in classes {
+            report.strings += size;
+            report.distinct += 1;
+            if size > 1 {
+                report.friendly += size;
+            } else {
+                report.singletons += 1;
+            }
+            report.friend_pairs += u64::from(size) * u64::from(size - 1) / 2;
+            *report.histogram.entry(size).or_insert(0) += 1;
+            // map iteration order is arbitrary, so ties are broken by pattern to keep this stable
+            if largest.is_none_or(|(top, most)| {
+                size > most || (size == most && compare_patterns(pattern, top) == Ordering::Less)
+            }) {
+                largest = Some((pattern, size));
+            }
+        }
+        report.largest = largest.map(|(pattern, size)| LargestClass {
+            pattern: pattern.into(),
+            size,
+        });
+        report
+    }
+}
+
+/// Count a collection of patterns, and summarise its pattern classes
+pub fn frequency_report<P>(patterns: &P) -> FrequencyReport
+where
+    P: PatternSource + ?Sized,
+{
+    frequency_report_with_hasher::<TrustedHasher, P>(patterns)
+}
+
+/// Count a collection of patterns using the given hasher, and summarise its pattern classes
+pub fn frequency_report_with_hasher<S, P>(patterns: &P) -> FrequencyReport
+where
+    S: BuildHasher + Default,
+    P: PatternSource + ?Sized,
+{
+    let mut frequency: HashMap<&[u8], u32, S> =
+        HashMap::with_capacity_and_hasher(patterns.pattern_count(), S::default());
+    (0..patterns.pattern_count())
+        .for_each(|idx| *frequency.entry(patterns.pattern(idx)).or_insert(0) += 1);
+    FrequencyReport::from_classes(frequency)
+}
@@ -0,0 +1,332 @@
+use crate::{PatternError, Symbols};
+use fnv::FnvHasher;
+use std::hash::Hasher;
+use std::io::{self, Read, Write};
+use std::ops::Index;
+
+/// The first bytes of a set written by `PatternSet::write_to`
+const MAGIC: &[u8; 8] = b"PATTERNS";
+
+/// What a set's patterns were generated from
+///
+/// This is written in the header of a stored set, so that a set generated from one corpus, or
+/// with one kind of symbols, can't silently be used with another: see `SetOrigin::check`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct SetOrigin {
+    /// The kind of symbols the patterns were generated with, e.g. "ascii", or "tokens:<regex>"
+    pub symbols: String,
+    /// An FNV-1a checksum of the strings the patterns were generated from, each followed by a newline
+    pub checksum: u64,
+}
+
+impl SetOrigin {
+    /// Describe the patterns of `strings`, generated using `symbols`
+    pub fn new(strings: &[&str], symbols: &Symbols) -> Self {
+        let mut hasher = FnvHasher::default();
+        strings.iter().for_each(|string| {
+            hasher.write(string.as_bytes());
+            hasher.write_u8(b'\n');
+        });
+        SetOrigin {
+            symbols: symbols_name(symbols),
+            checksum: hasher.finish(),
+        }
+    }
+
+    /// Check that a set with this origin was generated from `strings` using `symbols`
+    ///
+    /// Returns a `PatternError::IndexMismatch` if it wasn't.
+    pub fn check(&self, strings: &[&str], symbols: &Symbols) -> Result<(), PatternError> {
+        let expected = SetOrigin::new(strings, symbols);
+        if self.symbols != expected.symbols {
+            Err(PatternError::IndexMismatch(format!(
+                "it was generated with {} symbols, not {}",
+                self.symbols, expected.symbols
+            )))
+        } else if self.checksum != expected.checksum {
+            Err(PatternError::IndexMismatch(
+                "it was generated from different strings".to_string(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A stable name for a kind of symbols
+fn symbols_name(symbols: &Symbols) -> String {
+    match symbols {
+        Symbols::Ascii => "ascii".to_string(),
+        Symbols::Chars => "chars".to_string(),
+        Symbols::Graphemes => "graphemes".to_string(),
+        Symbols::Words => "words".to_string(),
+        Symbols::Tokens(separator) => format!("tokens:{}", separator.as_str()),
+    }
+}
+
+/// A collection of patterns which can be counted
+///
+/// This is implemented for slices and vecs of anything which can be viewed as bytes
+/// (such as the `Vec<Vec<u8>>` returned by `file_to_patterns`), and for `PatternSet`.
+pub trait PatternSource: Sync {
+    /// The number of patterns
+    fn pattern_count(&self) -> usize;
+    /// The pattern at `idx`, which must be less than `pattern_count()`
+    fn pattern(&self, idx: usize) -> &[u8];
+}
+
+impl<T> PatternSource for [T]
+where
+    T: AsRef<[u8]> + Sync,
+{
+    #[inline]
+    fn pattern_count(&self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn pattern(&self, idx: usize) -> &[u8] {
+        self[idx].as_ref()
+    }
+}
+
+impl<T> PatternSource for Vec<T>
+where
+    T: AsRef<[u8]> + Sync,
+{
+    #[inline]
+    fn pattern_count(&self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn pattern(&self, idx: usize) -> &[u8] {
+        self[idx].as_ref()
+    }
+}
+
+/// Patterns stored back-to-back in a single contiguous buffer
+///
+/// Each pattern is located by an offsets array, so a set holding a million patterns
+/// needs two allocations rather than a million. The buffer and offsets can be taken
+/// apart with `into_parts` and reassembled with `from_parts`, which makes a set cheap to serialize.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PatternSet {
+    data: Vec<u8>,
+    // offsets[i]..offsets[i + 1] is the range of pattern i, so there's always one more offset than pattern
+    offsets: Vec<usize>,
+}
+
+impl Default for PatternSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PatternSet {
+    pub fn new() -> Self {
+        Self::with_capacity(0, 0)
+    }
+
+    /// Create an empty set with space for `patterns` patterns, totalling `bytes` bytes
+    pub fn with_capacity(patterns: usize, bytes: usize) -> Self {
+        let mut offsets = Vec::with_capacity(patterns + 1);
+        offsets.push(0);
+        PatternSet {
+            data: Vec::with_capacity(bytes),
+            offsets,
+        }
+    }
+
+    /// Reassemble a set from a buffer and its offsets
+    ///
+    /// The offsets must start at 0, never decrease, and end at the length of the buffer.
+    /// Returns `None` if they don't.
+    pub fn from_parts(data: Vec<u8>, offsets: Vec<usize>) -> Option<Self> {
+        let valid = offsets.first() == Some(&0)
+            && offsets.last() == Some(&data.len())
+            && offsets.windows(2).all(|pair| pair[0] <= pair[1]);
+        valid.then_some(PatternSet { data, offsets })
+    }
+
+    /// Take a set apart into its buffer and offsets
+    pub fn into_parts(self) -> (Vec<u8>, Vec<usize>) {
+        (self.data, self.offsets)
+    }
+
+    /// Write a set and its origin, so that it can be read back with `read_from` instead of being rebuilt
+    ///
+    /// The format is a magic number, the origin's checksum, the length of its symbols name, the
+    /// number of patterns and the length of the buffer, followed by the symbols name, the offsets,
+    /// and then the buffer. Integers are 64-bit little-endian.
+    pub fn write_to<W: Write>(&self, origin: &SetOrigin, mut writer: W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&origin.checksum.to_le_bytes())?;
+        writer.write_all(&(origin.symbols.len() as u64).to_le_bytes())?;
+        writer.write_all(&(self.len() as u64).to_le_bytes())?;
+        writer.write_all(&(self.data.len() as u64).to_le_bytes())?;
+        writer.write_all(origin.symbols.as_bytes())?;
+        for &offset in &self.offsets {
+            writer.write_all(&(offset as u64).to_le_bytes())?;
+        }
+        writer.write_all(&self.data)?;
+        writer.flush()
+    }
+
+    /// Read a set written by `write_to`, along with its origin
+    ///
+    /// Returns an error of kind `InvalidData` if the input isn't a valid set.
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<(Self, SetOrigin)> {
+        let mut header = [0u8; 40];
+        match reader.read_exact(&mut header) {
+            Ok(()) if &header[..8] == MAGIC => (),
+            Ok(()) => return Err(invalid_set("missing header")),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(invalid_set("missing header"))
+            }
+            Err(err) => return Err(err),
+        }
+        let field = |idx: usize| u64::from_le_bytes(header[idx * 8..][..8].try_into().unwrap());
+        let (checksum, name, patterns, bytes) = (field(1), field(2), field(3), field(4));
+        // read through take, so that a corrupt length can't cause a huge allocation up front
+        let mut symbols = vec![];
+        (&mut reader).take(name).read_to_end(&mut symbols)?;
+        let mut offsets = vec![];
+        let expected = patterns
+            .checked_add(1)
+            .and_then(|offsets| offsets.checked_mul(8))
+            .ok_or_else(|| invalid_set("too many patterns"))?;
+        (&mut reader).take(expected).read_to_end(&mut offsets)?;
+        let mut data = vec![];
+        (&mut reader).take(bytes).read_to_end(&mut data)?;
+        if symbols.len() as u64 != name
+            || offsets.len() as u64 != expected
+            || data.len() as u64 != bytes
+        {
+            return Err(invalid_set("truncated"));
+        }
+        let origin = SetOrigin {
+            symbols: String::from_utf8(symbols).map_err(|_| invalid_set("invalid symbols name"))?,
+            checksum,
+        };
+        let offsets = offsets
+            .chunks_exact(8)
+            .map(|offset| u64::from_le_bytes(offset.try_into().unwrap()) as usize)
+            .collect();
+        let set =
+            PatternSet::from_parts(data, offsets).ok_or_else(|| invalid_set("invalid offsets"))?;
+        Ok((set, origin))
+    }
+
+    /// The buffer containing every pattern, back-to-back
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The offset of each pattern in the buffer, followed by the length of the buffer
+    pub fn offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    /// The number of patterns
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Append a pattern
+    pub fn push(&mut self, pattern: &[u8]) {
+        self.data.extend_from_slice(pattern);
+        self.offsets.push(self.data.len());
+    }
+
+    /// Append every pattern from another set
+    pub fn append(&mut self, other: &PatternSet) {
+        let base = self.data.len();
+        self.data.extend_from_slice(&other.data);
+        self.offsets
+            .extend(other.offsets[1..].iter().map(|offset| base + offset));
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&[u8]> {
+        (idx < self.len()).then(|| &self.data[self.offsets[idx]..self.offsets[idx + 1]])
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = &[u8]> + '_ {
+        self.offsets
+            .windows(2)
+            .map(move |pair| &self.data[pair[0]..pair[1]])
+    }
+
+    /// Append a pattern by writing it directly into the buffer
+    ///
+    /// If `write` fails, anything it wrote is discarded, and the set is unchanged.
+    pub(crate) fn push_with<E, F>(&mut self, write: F) -> Result<(), E>
+    where
+        F: FnOnce(&mut Vec<u8>) -> Result<(), E>,
+    {
+        let start = self.data.len();
+        match write(&mut self.data) {
+            Ok(()) => {
+                self.offsets.push(self.data.len());
+                Ok(())
+            }
+            Err(err) => {
+                self.data.truncate(start);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// An error reading a set
+fn invalid_set(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid pattern set: {}", reason),
+    )
+}
+
+impl Index<usize> for PatternSet {
+    type Output = [u8];
+
+    fn index(&self, idx: usize) -> &[u8] {
+        &self.data[self.offsets[idx]..self.offsets[idx + 1]]
+    }
+}
+
+impl PatternSource for PatternSet {
+    #[inline]
+    fn pattern_count(&self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn pattern(&self, idx: usize) -> &[u8] {
+        &self[idx]
+    }
+}
+
+impl<T> FromIterator<T> for PatternSet
+where
+    T: AsRef<[u8]>,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut set = PatternSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<T> Extend<T> for PatternSet
+where
+    T: AsRef<[u8]>,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        iter.into_iter()
+            .for_each(|pattern| self.push(pattern.as_ref()));
+    }
+}
@@ -0,0 +1,329 @@
+use crate::{
+    count_classes_in, lines_to_patterns, reject_lines, strip_line_ending, FrequencyReport,
+    LenientPatterns, PatternError, RejectedLine, Symbols, Threshold, TrustedHasher,
+};
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::io::{self, BufRead};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread;
+
+/// The number of lines read from a stream before they're handed on for processing
+pub const BATCH_SIZE: usize = 1 << 16;
+/// The number of batches which can be waiting to be processed
+const PIPELINE_DEPTH: usize = 2;
+
+/// The patterns generated from a batch of lines read from a stream
+#[derive(Debug, Default)]
+pub struct PatternBatch {
+    /// The line number of the first line in the batch
+    pub first_line: usize,
+    /// The number of lines in the batch, including rejected lines
+    pub lines: usize,
+    /// The patterns of the batch's valid lines, in line order
+    pub patterns: Vec<Vec<u8>>,
+    pub rejected: Vec<RejectedLine>,
+}
+
+/// The result of leniently counting a stream
+#[derive(Debug, Default)]
+pub struct LenientCount {
+    /// The number of friendly strings among the valid lines, or, if the stream was counted
+    /// with a threshold, the number of strings in the classes which met it
+    pub friendly: u32,
+    /// The number of pattern classes which met the threshold
+    pub classes: u32,
+    /// The number of lines read, including rejected lines
+    pub lines: usize,
+    /// The line numbers of the rejected lines, in order
+    pub rejected: Vec<usize>,
+}
+
+/// Read lines from any `BufRead`, and generate their patterns in parallel, a batch at a time
+///
+/// Lines are read on a separate thread while earlier batches are being processed, but only a
+/// few batches are held in memory at once, so memory use doesn't grow with the size of the input.
+/// Batches are passed to `f` in order. If `f` returns an error, reading stops and the error is returned.
+pub fn stream_patterns<R, F>(reader: R, symbols: Symbols, f: F) -> Result<(), PatternError>
+where
+    R: BufRead + Send,
+    F: FnMut(PatternBatch) -> Result<(), PatternError>,
+{
+    stream_patterns_with_hasher::<TrustedHasher, R, F>(reader, symbols, f)
+}
+
+/// Read lines from any `BufRead`, and generate their patterns in batches, hashing symbols with
+/// the given hasher
+pub fn stream_patterns_with_hasher<S, R, F>(
+    reader: R,
+    symbols: Symbols,
+    mut f: F,
+) -> Result<(), PatternError>
+where
+    S: BuildHasher + Default,
+    R: BufRead + Send,
+    F: FnMut(PatternBatch) -> Result<(), PatternError>,
+{
+    stream_lines::<S, R, _>(reader, symbols, |_, batch| f(batch))
+}
+
+/// Stream patterns in batches, as `stream_patterns` does, passing each batch's lines along with it
+pub(crate) fn stream_lines<S, R, F>(
+    reader: R,
+    symbols: Symbols,
+    mut f: F,
+) -> Result<(), PatternError>
+where
+    S: BuildHasher + Default,
+    R: BufRead + Send,
+    F: FnMut(&[Vec<u8>], PatternBatch) -> Result<(), PatternError>,
+{
+    let (sender, receiver) = sync_channel(PIPELINE_DEPTH);
+    thread::scope(|scope| {
+        scope.spawn(move || read_batches(reader, sender));
+        let mut first_line = 1;
+        // returning early drops the receiver, which stops the reading thread
+        for lines in receiver {
+            let lines = lines?;
+            let (patterns, errors) =
+                lines_to_patterns::<S, _, _>(&lines, |idx| first_line + idx, &symbols);
+            let rejected = reject_lines(errors, &lines, first_line);
+            f(
+                &lines,
+                PatternBatch {
+                    first_line,
+                    lines: lines.len(),
+                    patterns,
+                    rejected,
+                },
+            )?;
+            first_line += lines.len();
+        }
+        Ok(())
+    })
+}
+
+/// Read lines into batches, splitting them as `split_lines` does, and send them for processing
+fn read_batches<R>(mut reader: R, sender: SyncSender<io::Result<Vec<Vec<u8>>>>)
+where
+    R: BufRead,
+{
+    loop {
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        while batch.len() < BATCH_SIZE {
+            let mut line = Vec::new();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => {
+                    line.truncate(strip_line_ending(&line).len());
+                    batch.push(line)
+                }
+                Err(err) => {
+                    // the receiver may already have gone, in which case there's nobody to tell
+                    let _ = sender.send(Err(err));
+                    return;
+                }
+            }
+        }
+        let finished = batch.len() < BATCH_SIZE;
+        if (!batch.is_empty() && sender.send(Ok(batch)).is_err()) || finished {
+            return;
+        }
+    }
+}
+
+/// Read lines from any `BufRead`, and parse them into a vec of patterns
+///
+/// As with `file_to_patterns`, any invalid lines are returned in a `PatternError::InvalidLines`.
+pub fn reader_to_patterns<R>(reader: R, symbols: Symbols) -> Result<Vec<Vec<u8>>, PatternError>
+where
+    R: BufRead + Send,
+{
+    let parsed = reader_to_patterns_lenient(reader, symbols)?;
+    if parsed.rejected.is_empty() {
+        Ok(parsed.patterns)
+    } else {
+        Err(parsed.rejected.into())
+    }
+}
+
+/// Read lines from any `BufRead`, and parse the valid ones into a vec of patterns
+pub fn reader_to_patterns_lenient<R>(
+    reader: R,
+    symbols: Symbols,
+) -> Result<LenientPatterns, PatternError>
+where
+    R: BufRead + Send,
+{
+    let mut parsed = LenientPatterns::default();
+    stream_patterns(reader, symbols, |batch| {
+        parsed.patterns.extend(batch.patterns);
+        parsed.rejected.extend(batch.rejected);
+        Ok(())
+    })?;
+    Ok(parsed)
+}
+
+/// Count the friendly strings in a stream of lines, without holding the lines in memory
+///
+/// As with `file_to_patterns`, any invalid lines are returned in a `PatternError::InvalidLines`.
+pub fn count_reader<R>(reader: R, symbols: Symbols) -> Result<u32, PatternError>
+where
+    R: BufRead + Send,
+{
+    count_reader_with_hasher::<TrustedHasher, R>(reader, symbols)
+}
+
+/// Count the friendly strings in a stream of lines using the given hasher
+///
+/// Use `UntrustedHasher` for input from untrusted sources.
+pub fn count_reader_with_hasher<S, R>(reader: R, symbols: Symbols) -> Result<u32, PatternError>
+where
+    S: BuildHasher + Default + Sync,
+    R: BufRead + Send,
+{
+    let mut errors = vec![];
+    let counted = count_reader_classes_with_hasher::<S, R, _>(
+        reader,
+        symbols,
+        &Threshold::default(),
+        |reject| {
+            errors.push(reject.error);
+            Ok(())
+        },
+    )?;
+    if errors.is_empty() {
+        Ok(counted.friendly)
+    } else {
+        Err(PatternError::InvalidLines(errors))
+    }
+}
+
+/// Count the friendly strings among the valid lines of a stream, without holding the lines in memory
+///
+/// Only the line numbers of rejected lines are kept: use `count_reader_classes` to see their contents.
+pub fn count_reader_lenient<R>(reader: R, symbols: Symbols) -> Result<LenientCount, PatternError>
+where
+    R: BufRead + Send,
+{
+    count_reader_lenient_with_hasher::<TrustedHasher, R>(reader, symbols)
+}
+
+/// Count the friendly strings among the valid lines of a stream using the given hasher
+pub fn count_reader_lenient_with_hasher<S, R>(
+    reader: R,
+    symbols: Symbols,
+) -> Result<LenientCount, PatternError>
+where
+    S: BuildHasher + Default + Sync,
+    R: BufRead + Send,
+{
+    count_reader_classes_with_hasher::<S, R, _>(reader, symbols, &Threshold::default(), |_| Ok(()))
+}
+
+/// Count the pattern classes among the valid lines of a stream whose sizes meet a threshold,
+/// and the strings belonging to them
+///
+/// Each rejected line is passed to `on_reject` as soon as its batch has been parsed, so that it
+/// can be written out without every rejected line being held in memory. If `on_reject` returns
+/// an error, reading stops and the error is returned.
+pub fn count_reader_classes<R, F>(
+    reader: R,
+    symbols: Symbols,
+    threshold: &Threshold,
+    on_reject: F,
+) -> Result<LenientCount, PatternError>
+where
+    R: BufRead + Send,
+    F: FnMut(RejectedLine) -> Result<(), PatternError>,
+{
+    count_reader_classes_with_hasher::<TrustedHasher, R, F>(reader, symbols, threshold, on_reject)
+}
+
+/// Count the pattern classes among the valid lines of a stream using the given hasher
+pub fn count_reader_classes_with_hasher<S, R, F>(
+    reader: R,
+    symbols: Symbols,
+    threshold: &Threshold,
+    on_reject: F,
+) -> Result<LenientCount, PatternError>
+where
+    S: BuildHasher + Default + Sync,
+    R: BufRead + Send,
+    F: FnMut(RejectedLine) -> Result<(), PatternError>,
+{
+    let mut counted = LenientCount::default();
+    let frequency: HashMap<Vec<u8>, u32, S> =
+        count_stream(reader, symbols, &mut counted, on_reject)?;
+    let classes = count_classes_in(&frequency, threshold);
+    counted.friendly = classes.strings;
+    counted.classes = classes.classes;
+    Ok(counted)
+}
+
+/// Summarise the pattern classes among the valid lines of a stream, without holding the lines in memory
+///
+/// The report covers only the valid lines. Rejected lines are passed to `on_reject` as
+/// `count_reader_classes` passes them, and their line numbers are returned alongside the report.
+pub fn report_reader_lenient<R, F>(
+    reader: R,
+    symbols: Symbols,
+    on_reject: F,
+) -> Result<(FrequencyReport, Vec<usize>), PatternError>
+where
+    R: BufRead + Send,
+    F: FnMut(RejectedLine) -> Result<(), PatternError>,
+{
+    report_reader_lenient_with_hasher::<TrustedHasher, R, F>(reader, symbols, on_reject)
+}
+
+/// Summarise the pattern classes among the valid lines of a stream using the given hasher
+pub fn report_reader_lenient_with_hasher<S, R, F>(
+    reader: R,
+    symbols: Symbols,
+    on_reject: F,
+) -> Result<(FrequencyReport, Vec<usize>), PatternError>
+where
+    S: BuildHasher + Default,
+    R: BufRead + Send,
+    F: FnMut(RejectedLine) -> Result<(), PatternError>,
+{
+    let mut counted = LenientCount::default();
+    let frequency: HashMap<Vec<u8>, u32, S> =
+        count_stream(reader, symbols, &mut counted, on_reject)?;
+    let report = FrequencyReport::from_classes(
+        frequency
+            .iter()
+            .map(|(pattern, &size)| (pattern.as_slice(), size)),
+    );
+    Ok((report, counted.rejected))
+}
+
+/// Build a frequency count of the patterns in a stream, recording the lines read and the line
+/// numbers of rejected lines, and handing each rejected line to `on_reject`
+fn count_stream<S, R, F>(
+    reader: R,
+    symbols: Symbols,
+    counted: &mut LenientCount,
+    mut on_reject: F,
+) -> Result<HashMap<Vec<u8>, u32, S>, PatternError>
+where
+    S: BuildHasher + Default,
+    R: BufRead + Send,
+    F: FnMut(RejectedLine) -> Result<(), PatternError>,
+{
+    let mut frequency: HashMap<Vec<u8>, u32, S> = HashMap::default();
+    stream_patterns_with_hasher::<S, R, _>(reader, symbols, |batch| {
+        batch
+            .patterns
+            .into_iter()
+            .for_each(|pattern| *frequency.entry(pattern).or_insert(0) += 1);
+        counted.lines += batch.lines;
+        for reject in batch.rejected {
+            counted.rejected.push(reject.line);
+            on_reject(reject)?;
+        }
+        Ok(())
+    })?;
+    Ok(frequency)
+}
@@ -0,0 +1,80 @@
+use std::iter::Sum;
+use std::ops::Add;
+
+/// The pattern class sizes which are counted
+///
+/// A class of `n` members gives each of its strings `n - 1` friends, so the default threshold,
+/// which counts classes of at least two members, counts every string with at least one friend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Threshold {
+    /// The smallest class size which is counted
+    pub min: u32,
+    /// The largest class size which is counted, if any
+    pub max: Option<u32>,
+}
+
+impl Default for Threshold {
+    fn default() -> Self {
+        Threshold::at_least(2)
+    }
+}
+
+impl Threshold {
+    /// Count classes of at least `min` members
+    pub fn at_least(min: u32) -> Self {
+        Threshold { min, max: None }
+    }
+
+    /// Count classes of at least `min` and at most `max` members
+    pub fn between(min: u32, max: u32) -> Self {
+        Threshold {
+            min,
+            max: Some(max),
+        }
+    }
+
+    /// Whether a class of `size` members is counted
+    #[inline]
+    pub fn contains(&self, size: u32) -> bool {
+        size >= self.min && self.max.is_none_or(|max| size <= max)
+    }
+
+    /// The count for a single class of `size` members: either one class, or nothing
+    #[inline]
+    pub(crate) fn tally(&self, size: u32) -> ClassCount {
+        if self.contains(size) {
+            ClassCount {
+                strings: size,
+                classes: 1,
+            }
+        } else {
+            ClassCount::default()
+        }
+    }
+}
+
+/// The pattern classes which met a `Threshold`, and the strings belonging to them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ClassCount {
+    /// The number of strings in the counted classes
+    pub strings: u32,
+    /// The number of counted classes
+    pub classes: u32,
+}
+
+impl Add for ClassCount {
+    type Output = ClassCount;
+
+    fn add(self, other: ClassCount) -> ClassCount {
+        ClassCount {
+            strings: self.strings + other.strings,
+            classes: self.classes + other.classes,
+        }
+    }
+}
+
+impl Sum for ClassCount {
+    fn sum<I: Iterator<Item = ClassCount>>(iter: I) -> Self {
+        iter.fold(ClassCount::default(), Add::add)
+    }
+}
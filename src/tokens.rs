@@ -0,0 +1,35 @@
+use crate::{generate_pattern_of_with_hasher, TrustedHasher};
+use regex::Regex;
+use std::hash::BuildHasher;
+
+/// Generate a pattern from a string, treating each whitespace-separated word as a symbol
+// "the cat saw the dog" generates a pattern of 01203
+// "a man met a woman" generates a pattern of 01203
+pub fn generate_word_pattern(haystack: &str) -> Vec<u8> {
+    generate_word_pattern_with_hasher::<TrustedHasher>(haystack)
+}
+
+/// Generate a pattern from a string's whitespace-separated words using the given hasher
+pub fn generate_word_pattern_with_hasher<S>(haystack: &str) -> Vec<u8>
+where
+    S: BuildHasher + Default,
+{
+    generate_pattern_of_with_hasher::<S, _, _>(haystack.split_whitespace())
+}
+
+/// Generate a pattern from a string, treating each token between matches of `separator` as a symbol
+///
+/// Empty tokens (e.g. from leading or repeated separators) are ignored.
+pub fn generate_token_pattern(haystack: &str, separator: &Regex) -> Vec<u8> {
+    generate_token_pattern_with_hasher::<TrustedHasher>(haystack, separator)
+}
+
+/// Generate a pattern from the tokens between matches of `separator` using the given hasher
+pub fn generate_token_pattern_with_hasher<S>(haystack: &str, separator: &Regex) -> Vec<u8>
+where
+    S: BuildHasher + Default,
+{
+    generate_pattern_of_with_hasher::<S, _, _>(
+        separator.split(haystack).filter(|token| !token.is_empty()),
+    )
+}
@@ -0,0 +1,224 @@
+use crate::stream::stream_lines;
+use crate::{checked_patterns, Pattern, PatternError, Symbols, TrustedHasher};
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::BuildHasher;
+use std::io::BufRead;
+
+/// The number of example members kept for each of the most common patterns
+pub const TOP_EXAMPLES: usize = 3;
+
+/// One of the most common patterns
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TopPattern {
+    pub pattern: Pattern,
+    /// The number of strings with this pattern. For streamed input, this is an upper bound
+    pub count: u32,
+    /// The most by which `count` can exceed the true count: always 0 unless the input was streamed
+    pub overestimate: u32,
+    /// Up to `TOP_EXAMPLES` strings with this pattern
+    pub examples: Vec<String>,
+}
+
+impl TopPattern {
+    fn new(pattern: Vec<u8>, count: u32, overestimate: u32, examples: Vec<String>) -> Self {
+        TopPattern {
+            pattern: pattern.into(),
+            count,
+            overestimate,
+            examples,
+        }
+    }
+}
+
+/// Find the `n` most common patterns among strings, with exact counts
+///
+/// Patterns are ordered by descending count, and ties by pattern. Examples are the first
+/// `TOP_EXAMPLES` strings with each pattern.
+pub fn top_patterns(
+    strings: &[&str],
+    symbols: Symbols,
+    n: usize,
+) -> Result<Vec<TopPattern>, PatternError> {
+    top_patterns_with_hasher::<TrustedHasher>(strings, symbols, n)
+}
+
+/// Find the `n` most common patterns among strings using the given hasher
+pub fn top_patterns_with_hasher<S>(
+    strings: &[&str],
+    symbols: Symbols,
+    n: usize,
+) -> Result<Vec<TopPattern>, PatternError>
+where
+    S: BuildHasher + Default,
+{
+    let patterns = checked_patterns::<S, _>(strings, &symbols)?;
+    let mut frequency: HashMap<&[u8], (u32, Vec<&str>), S> =
+        HashMap::with_capacity_and_hasher(patterns.len(), S::default());
+    patterns.iter().zip(strings).for_each(|(pattern, string)| {
+        let (count, examples) = frequency.entry(pattern).or_default();
+        *count += 1;
+        if examples.len() < TOP_EXAMPLES {
+            examples.push(string)
+        }
+    });
+    let mut top: Vec<TopPattern> = frequency
+        .into_iter()
+        .map(|(pattern, (count, examples))| {
+            TopPattern::new(
+                pattern.to_vec(),
+                count,
+                0,
+                examples.into_iter().map(str::to_string).collect(),
+            )
+        })
+        .collect();
+    sort_top(&mut top, n);
+    Ok(top)
+}
+
+/// Find the `n` most common patterns in a stream of lines, using a bounded amount of memory
+///
+/// No more than `capacity` (or `n`, if it's larger) patterns are tracked at once, using the
+/// Space-Saving algorithm: when a new pattern arrives and every slot is taken, it replaces the
+/// least common tracked pattern, inheriting its count. Any pattern occurring more than
+/// (lines / capacity) times is guaranteed to be found, and each count is overestimated by at most
+/// `overestimate`. A capacity of 10–100 times `n` is usually enough for exact results on skewed input.
+///
+/// The number of lines read is returned alongside the patterns. Reading stops at the first batch
+/// containing invalid lines, which are returned in a `PatternError::InvalidLines`.
+pub fn top_patterns_reader<R>(
+    reader: R,
+    symbols: Symbols,
+    n: usize,
+    capacity: usize,
+) -> Result<(Vec<TopPattern>, usize), PatternError>
+where
+    R: BufRead + Send,
+{
+    top_patterns_reader_with_hasher::<TrustedHasher, R>(reader, symbols, n, capacity)
+}
+
+/// Find the `n` most common patterns in a stream of lines using the given hasher
+pub fn top_patterns_reader_with_hasher<S, R>(
+    reader: R,
+    symbols: Symbols,
+    n: usize,
+    capacity: usize,
+) -> Result<(Vec<TopPattern>, usize), PatternError>
+where
+    S: BuildHasher + Default,
+    R: BufRead + Send,
+{
+    let mut summary: SpaceSaving<S> = SpaceSaving::new(capacity.max(n).max(1));
+    let mut read = 0;
+    stream_lines::<S, R, _>(reader, symbols, |lines, batch| {
+        // stop at the first batch with invalid lines, rather than holding every one of them
+        if !batch.rejected.is_empty() {
+            return Err(batch.rejected.into());
+        }
+        for (pattern, line) in batch.patterns.iter().zip(lines) {
+            summary.insert(pattern, line);
+        }
+        read += batch.lines;
+        Ok(())
+    })?;
+    let mut top: Vec<TopPattern> = summary
+        .counters
+        .into_iter()
+        .map(|counter| {
+            TopPattern::new(
+                counter.pattern,
+                counter.count,
+                counter.overestimate,
+                counter.examples,
+            )
+        })
+        .collect();
+    sort_top(&mut top, n);
+    Ok((top, read))
+}
+
+/// Order patterns by descending count, and ties by pattern, keeping the first `n`
+fn sort_top(top: &mut Vec<TopPattern>, n: usize) {
+    top.par_sort_unstable_by(|left, right| {
+        right
+            .count
+            .cmp(&left.count)
+            .then_with(|| left.pattern.cmp(&right.pattern))
+    });
+    top.truncate(n);
+}
+
+/// A tracked pattern
+struct Counter {
+    pattern: Vec<u8>,
+    count: u32,
+    overestimate: u32,
+    examples: Vec<String>,
+}
+
+/// A Space-Saving summary: a fixed number of counters, which the least common pattern gives up
+/// whenever an untracked pattern arrives
+struct SpaceSaving<S> {
+    capacity: usize,
+    counters: Vec<Counter>,
+    // the counter tracking each pattern
+    slots: HashMap<Vec<u8>, usize, S>,
+    // counters ordered by count, so that the least common is always first
+    order: BTreeSet<(u32, usize)>,
+}
+
+impl<S> SpaceSaving<S>
+where
+    S: BuildHasher + Default,
+{
+    fn new(capacity: usize) -> Self {
+        SpaceSaving {
+            capacity,
+            counters: Vec::with_capacity(capacity),
+            slots: HashMap::with_capacity_and_hasher(capacity, S::default()),
+            order: BTreeSet::new(),
+        }
+    }
+
+    fn insert(&mut self, pattern: &[u8], line: &[u8]) {
+        if let Some(&slot) = self.slots.get(pattern) {
+            let counter = &mut self.counters[slot];
+            self.order.remove(&(counter.count, slot));
+            counter.count += 1;
+            self.order.insert((counter.count, slot));
+            if counter.examples.len() < TOP_EXAMPLES {
+                counter.examples.push(example(line));
+            }
+        } else if self.counters.len() < self.capacity {
+            let slot = self.counters.len();
+            self.counters.push(Counter {
+                pattern: pattern.to_vec(),
+                count: 1,
+                overestimate: 0,
+                examples: vec![example(line)],
+            });
+            self.slots.insert(pattern.to_vec(), slot);
+            self.order.insert((1, slot));
+        } else {
+            let (least, slot) = self.order.pop_first().unwrap();
+            let counter = &mut self.counters[slot];
+            self.slots.remove(&counter.pattern);
+            // the evicted pattern's examples don't belong to the new one
+            *counter = Counter {
+                pattern: pattern.to_vec(),
+                count: least + 1,
+                overestimate: least,
+                examples: vec![example(line)],
+            };
+            self.slots.insert(pattern.to_vec(), slot);
+            self.order.insert((least + 1, slot));
+        }
+    }
+}
+
+/// Copy a line as an example: any line which produced a pattern is valid UTF-8, so nothing is lost
+fn example(line: &[u8]) -> String {
+    String::from_utf8_lossy(line).into_owned()
+}
@@ -0,0 +1,36 @@
+use crate::{generate_pattern_of_with_hasher, TrustedHasher};
+use std::hash::BuildHasher;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Generate a pattern from a string of arbitrary Unicode characters
+///
+/// Each `char` is a symbol, so precomposed and decomposed forms of the same
+/// accented letter produce different patterns. Any number of distinct
+/// characters can be mapped: see `generate_pattern_of` for the encoding.
+pub fn generate_char_pattern(haystack: &str) -> Vec<u8> {
+    generate_char_pattern_with_hasher::<TrustedHasher>(haystack)
+}
+
+/// Generate a pattern from a string of arbitrary Unicode characters using the given hasher
+pub fn generate_char_pattern_with_hasher<S>(haystack: &str) -> Vec<u8>
+where
+    S: BuildHasher + Default,
+{
+    generate_pattern_of_with_hasher::<S, _, _>(haystack.chars())
+}
+
+/// Generate a pattern from a string, treating each extended grapheme cluster as a symbol
+///
+/// This is slower than `generate_char_pattern`, but "é" is a single symbol
+/// whether or not it's precomposed.
+pub fn generate_grapheme_pattern(haystack: &str) -> Vec<u8> {
+    generate_grapheme_pattern_with_hasher::<TrustedHasher>(haystack)
+}
+
+/// Generate a pattern from a string's extended grapheme clusters using the given hasher
+pub fn generate_grapheme_pattern_with_hasher<S>(haystack: &str) -> Vec<u8>
+where
+    S: BuildHasher + Default,
+{
+    generate_pattern_of_with_hasher::<S, _, _>(haystack.graphemes(true))
+}